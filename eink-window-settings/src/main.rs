@@ -7,10 +7,10 @@ use std::{
 
 use eframe::egui;
 use enum2egui::GuiInspect;
-use quill_data_provider_lib::{EinkWindowSetting, load_window_settings};
+use quill_data_provider_lib::{EinkPreset, EinkWindowSetting, load_presets, load_window_settings};
 
 #[cfg(not(target_arch = "x86_64"))]
-use quill_data_provider_lib::{WINDOW_SETTINGS_CONFIG_NAME, WINDOW_SETTINGS_HOME_CONFIG_DIR};
+use quill_data_provider_lib::{PRESETS_CONFIG_NAME, WINDOW_SETTINGS_CONFIG_NAME, home_config_path};
 
 use crate::style::style;
 
@@ -26,6 +26,28 @@ fn save_settings(
         }
     }
 
+    write_ron(settings, path)?;
+    Ok(format!("Succesfully saved settings").into())
+}
+
+fn save_presets(
+    presets: &Vec<EinkPreset>,
+    path: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    for (i, preset) in presets.iter().enumerate() {
+        if preset.name.is_empty() {
+            return Err(format!("Preset name at index {} is empty", i).into());
+        }
+        if presets[..i].iter().any(|p| p.name == preset.name) {
+            return Err(format!("Preset name {} is used more than once", preset.name).into());
+        }
+    }
+
+    write_ron(presets, path)?;
+    Ok("Successfully saved presets".to_string())
+}
+
+fn write_ron<T: serde::Serialize>(value: &T, path: &str) -> Result<(), Box<dyn std::error::Error>> {
     if let Some(parent) = std::path::Path::new(&path).parent() {
         match std::fs::create_dir_all(parent) {
             Ok(_) => {}
@@ -35,9 +57,9 @@ fn save_settings(
         }
     }
 
-    let ron = ron::ser::to_string_pretty(settings, ron::ser::PrettyConfig::default())?;
+    let ron = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
     std::fs::write(path, ron)?;
-    Ok(format!("Succesfully saved settings").into())
+    Ok(())
 }
 
 fn main() -> eframe::Result {
@@ -74,31 +96,34 @@ fn main() -> eframe::Result {
 
     #[allow(unused)]
     let mut path: String = String::new();
+    #[allow(unused)]
+    let mut presets_path: String = String::new();
     // So it opens the ones in the repo here. Yes, it does not support arm mac
     #[cfg(target_arch = "x86_64")]
     {
         path = "other/default/config.ron".to_string();
+        presets_path = "other/default/presets.ron".to_string();
     }
     #[cfg(not(target_arch = "x86_64"))]
     {
-        let username = std::env::var("USER").unwrap_or_default();
-        path = format!(
-            "/home/{}{}{}",
-            username, WINDOW_SETTINGS_HOME_CONFIG_DIR, WINDOW_SETTINGS_CONFIG_NAME
-        )
-        .to_string();
+        path = home_config_path(WINDOW_SETTINGS_CONFIG_NAME);
+        presets_path = home_config_path(PRESETS_CONFIG_NAME);
     }
 
     println!("Path for settings is: {}", path);
+    println!("Path for presets is: {}", presets_path);
     let settings = load_window_settings(path.to_string());
+    let presets = load_presets(presets_path.to_string());
 
     let app = MyApp {
         settings: settings,
+        presets,
         windows_rx: rx,
         windows: Vec::new(),
         zoom_factor: 1.2,
         window_message: None,
         save_settings_path: path.to_string(),
+        save_presets_path: presets_path.to_string(),
     };
 
     eframe::run_native(
@@ -116,11 +141,13 @@ fn main() -> eframe::Result {
 
 struct MyApp {
     settings: Vec<EinkWindowSetting>,
+    presets: Vec<EinkPreset>,
     windows_rx: Receiver<Vec<String>>,
     windows: Vec<String>,
     zoom_factor: f32,
     window_message: Option<String>,
     save_settings_path: String,
+    save_presets_path: String,
 }
 
 impl eframe::App for MyApp {
@@ -128,11 +155,17 @@ impl eframe::App for MyApp {
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
             egui::MenuBar::new().ui(ui, |ui| {
                 if ui.button("Save").clicked() {
-                    let status = save_settings(&self.settings, &self.save_settings_path);
-                    match status {
-                        Ok(x) => self.window_message = Some(x),
-                        Err(x) => self.window_message = Some(x.to_string()),
-                    }
+                    let messages: Vec<String> = [
+                        save_settings(&self.settings, &self.save_settings_path),
+                        save_presets(&self.presets, &self.save_presets_path),
+                    ]
+                    .into_iter()
+                    .map(|status| match status {
+                        Ok(x) => x,
+                        Err(x) => x.to_string(),
+                    })
+                    .collect();
+                    self.window_message = Some(messages.join("\n"));
                 }
                 if ui.button("Zoom in").clicked() {
                     self.zoom_factor *= 1.2;
@@ -164,12 +197,16 @@ impl eframe::App for MyApp {
                 // .scroll_bar_visibility(egui::scroll_area::ScrollBarVisibility::AlwaysVisible)
                 .show(ui, |ui| {
                     ui.heading("eInk window settings");
-                    ui.label("Values which are global, but will be set for the currently focused window (or defaults if not apply, for the focused window):
+                    ui.label("Values which are driver wide. With per window settings on, they are set again on every focus change, from the settings of the focused window (or from the default preset for windows without settings):
 - Treshold level
 - Dithering type
 - Redraw delay
 - Fast mode");
                     self.settings.ui_mut(ui);
+                    ui.separator();
+                    ui.heading("eInk presets");
+                    ui.label("Named modes which can be applied or cycled through with requests. The first one is applied when the data provider starts.");
+                    self.presets.ui_mut(ui);
                 });
         });
 
@@ -8,4 +8,53 @@ pub enum Requests {
     ScreenRefresh,
     ScreenSettings,
     SmallScreenSettings,
+    ApplyPreset(String),
+    NextPreset,
+    PreviousPreset,
+    // Preset name or a DriverMode in ron, like "Fast(Bayer)"
+    OverrideMode(String, OverrideUntil),
+    // In tenths, 10 is a gamma of 1.0
+    Gamma(u8),
+    // Re-reads the provider config file
+    ReloadConfig,
+    // Hours back from now, answered on the battery_history topic
+    BatteryHistory(u32),
+    SetBacklight(BacklightChannel, BacklightChange),
+    SetFrontlight(FrontlightChange),
+    // Frontlight brightness from the ambient light sensor
+    AutoBrightness(bool),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone, Copy)]
+pub enum OverrideUntil {
+    Seconds(u32),
+    FocusChange,
+    // Milliseconds without any input
+    InputIdle(u32),
+    // Milliseconds after the pen left proximity
+    PenAway(u32),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone, Copy)]
+pub enum BacklightChannel {
+    Cool,
+    Warm,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone, Copy)]
+pub enum BacklightChange {
+    // In driver units, up to max_brightness
+    Absolute(u32),
+    // Added to the current value, in driver units
+    Step(i32),
+    Percent(u8),
+}
+
+// Percent, the temperature goes from cool to warm
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone, Copy)]
+pub enum FrontlightChange {
+    Brightness(u8),
+    BrightnessStep(i32),
+    Temperature(u8),
+    TemperatureStep(i32),
 }
@@ -1,4 +1,4 @@
-use enums::Requests;
+use enums::{BacklightChange, BacklightChannel, FrontlightChange, OverrideUntil, Requests};
 use std::env;
 use std::fs;
 use std::io::{self, BufRead, BufReader, Write};
@@ -12,6 +12,38 @@ fn help() {
     eprintln!("Commands:");
     eprintln!("  listen <socket_name> - Listen on a Unix socket and print incoming lines.");
     eprintln!("  send <request_type>  - Send a request enum to the data provider.");
+    eprintln!("  send preset <name>   - Apply a named eInk preset.");
+    eprintln!("  send next_preset     - Apply the next eInk preset.");
+    eprintln!("  send previous_preset - Apply the previous eInk preset.");
+    eprintln!("  send reload_config   - Read the provider's config files again.");
+    eprintln!("  send gamma <tenths>  - Set the screen gamma, 10 is neutral.");
+    eprintln!("  send battery_history <hours> - Publish the battery history of the last hours.");
+    eprintln!(
+        "  send backlight <cool | warm> <value | +step | -step | percent%> - Set a backlight."
+    );
+    eprintln!(
+        "  send frontlight <brightness | temperature> <percent | +step | -step> - Set both backlights together."
+    );
+    eprintln!("  send auto_brightness <on | off> - Follow the ambient light sensor.");
+    eprintln!(
+        "  send override <preset or mode> <seconds | focus | idle <ms> | pen <ms>> - Temporarily change the eInk mode."
+    );
+    std::process::exit(1);
+}
+
+fn parse_backlight_change(value: &str) -> BacklightChange {
+    let change = if let Some(percent) = value.strip_suffix('%') {
+        percent.parse().ok().map(BacklightChange::Percent)
+    } else if value.starts_with('+') || value.starts_with('-') {
+        value.parse().ok().map(BacklightChange::Step)
+    } else {
+        value.parse().ok().map(BacklightChange::Absolute)
+    };
+    change.unwrap_or_else(|| help_exit("Bad backlight value"))
+}
+
+fn help_exit(message: &str) -> ! {
+    eprintln!("{}", message);
     std::process::exit(1);
 }
 
@@ -76,6 +108,85 @@ fn main() -> io::Result<()> {
                 "refresh" => Requests::ScreenRefresh,
                 "screen_settings" => Requests::ScreenSettings,
                 "small_screen_settings" => Requests::SmallScreenSettings,
+                "preset" => {
+                    if args.len() < 4 {
+                        help();
+                    }
+                    Requests::ApplyPreset(args[3].clone())
+                }
+                "next_preset" => Requests::NextPreset,
+                "gamma" => match args.get(3).map(|gamma| gamma.parse()) {
+                    Some(Ok(gamma)) => Requests::Gamma(gamma),
+                    _ => help_exit("gamma needs a number of tenths"),
+                },
+                "battery_history" => match args.get(3).map(|hours| hours.parse()) {
+                    Some(Ok(hours)) => Requests::BatteryHistory(hours),
+                    _ => help_exit("battery_history needs a number of hours"),
+                },
+                "backlight" => {
+                    if args.len() < 5 {
+                        help();
+                    }
+                    let channel = match args[3].as_str() {
+                        "cool" => BacklightChannel::Cool,
+                        "warm" => BacklightChannel::Warm,
+                        _ => help_exit("Unknown backlight channel"),
+                    };
+                    Requests::SetBacklight(channel, parse_backlight_change(&args[4]))
+                }
+                "frontlight" => {
+                    if args.len() < 5 {
+                        help();
+                    }
+                    let value = &args[4];
+                    let is_step = value.starts_with('+') || value.starts_with('-');
+                    let change = match (args[3].as_str(), is_step) {
+                        ("brightness", false) => {
+                            value.parse().ok().map(FrontlightChange::Brightness)
+                        }
+                        ("brightness", true) => {
+                            value.parse().ok().map(FrontlightChange::BrightnessStep)
+                        }
+                        ("temperature", false) => {
+                            value.parse().ok().map(FrontlightChange::Temperature)
+                        }
+                        ("temperature", true) => {
+                            value.parse().ok().map(FrontlightChange::TemperatureStep)
+                        }
+                        _ => help_exit("Unknown frontlight axis"),
+                    };
+                    Requests::SetFrontlight(
+                        change.unwrap_or_else(|| help_exit("Bad frontlight value")),
+                    )
+                }
+                "auto_brightness" => match args.get(3).map(String::as_str) {
+                    Some("on") => Requests::AutoBrightness(true),
+                    Some("off") => Requests::AutoBrightness(false),
+                    _ => help_exit("auto_brightness needs on or off"),
+                },
+                "previous_preset" => Requests::PreviousPreset,
+                "reload_config" => Requests::ReloadConfig,
+                "override" => {
+                    if args.len() < 5 {
+                        help();
+                    }
+                    let until = match args[4].as_str() {
+                        "focus" => OverrideUntil::FocusChange,
+                        "idle" => match args.get(5).map(|ms| ms.parse()) {
+                            Some(Ok(ms)) => OverrideUntil::InputIdle(ms),
+                            _ => help_exit("idle needs a number of milliseconds"),
+                        },
+                        "pen" => match args.get(5).map(|ms| ms.parse()) {
+                            Some(Ok(ms)) => OverrideUntil::PenAway(ms),
+                            _ => help_exit("pen needs a number of milliseconds"),
+                        },
+                        seconds => match seconds.parse() {
+                            Ok(seconds) => OverrideUntil::Seconds(seconds),
+                            Err(_) => help_exit("Unknown override duration"),
+                        },
+                    };
+                    Requests::OverrideMode(args[3].clone(), until)
+                }
                 _ => {
                     eprintln!("Unknown request type: {}", request_type);
                     std::process::exit(1);
@@ -0,0 +1,659 @@
+// The driver side of a DriverMode: the render hint, the dbus properties and the
+// sysfs threshold. DriverState is the one conversion between the two
+use log::{debug, warn};
+use std::{fmt, io, str::FromStr};
+
+use crate::{
+    try_run_cmd, BitDepth, Conversion, Dithering, DriverMode, Redraw, RedrawOptions, ThresholdLevel,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PureBitDepth {
+    Y1,
+    Y2,
+    Y4,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PureConversion {
+    Thresholding,
+    Dithering,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PureRedraw {
+    FastDrawing,        // R
+    DisableFastDrawing, // r
+}
+
+// Impl string functions for render hint enums
+impl fmt::Display for PureRedraw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PureRedraw::FastDrawing => write!(f, "R"),
+            PureRedraw::DisableFastDrawing => write!(f, "r"),
+        }
+    }
+}
+
+impl FromStr for PureRedraw {
+    type Err = RenderHintError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "R" => Ok(PureRedraw::FastDrawing),
+            "r" => Ok(PureRedraw::DisableFastDrawing),
+            _ => Err(RenderHintError::UnknownFlag(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for PureBitDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PureBitDepth::Y1 => write!(f, "Y1"),
+            PureBitDepth::Y2 => write!(f, "Y2"),
+            PureBitDepth::Y4 => write!(f, "Y4"),
+        }
+    }
+}
+
+impl FromStr for PureBitDepth {
+    type Err = RenderHintError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Y1" => Ok(PureBitDepth::Y1),
+            "Y2" => Ok(PureBitDepth::Y2),
+            "Y4" => Ok(PureBitDepth::Y4),
+            _ => Err(RenderHintError::UnknownFlag(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for PureConversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PureConversion::Thresholding => write!(f, "T"),
+            PureConversion::Dithering => write!(f, "D"),
+        }
+    }
+}
+
+impl FromStr for PureConversion {
+    type Err = RenderHintError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "T" => Ok(PureConversion::Thresholding),
+            "D" => Ok(PureConversion::Dithering),
+            _ => Err(RenderHintError::UnknownFlag(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderHintError {
+    Empty,
+    UnknownFlag(String),
+    // Two flags for the same part, like "Y1|Y2"
+    Duplicate(HintFlag, HintFlag),
+    MissingBitDepth,
+    MissingConversion,
+    MissingRedraw,
+}
+
+impl fmt::Display for RenderHintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderHintError::Empty => write!(f, "Render hint is empty"),
+            RenderHintError::UnknownFlag(flag) => write!(f, "Unknown render hint flag {:?}", flag),
+            RenderHintError::Duplicate(first, second) => {
+                write!(f, "Render hint flags {} and {} conflict", first, second)
+            }
+            RenderHintError::MissingBitDepth => {
+                write!(f, "Render hint has no bit depth (Y1/Y2/Y4)")
+            }
+            RenderHintError::MissingConversion => write!(f, "Render hint has no conversion (T/D)"),
+            RenderHintError::MissingRedraw => write!(f, "Render hint has no redraw (R/r)"),
+        }
+    }
+}
+
+impl std::error::Error for RenderHintError {}
+
+// One of the |-separated parts of a hint, every flag the driver accepts
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HintFlag {
+    BitDepth(PureBitDepth),
+    Conversion(PureConversion),
+    Redraw(PureRedraw),
+}
+
+impl fmt::Display for HintFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HintFlag::BitDepth(bit_depth) => write!(f, "{}", bit_depth),
+            HintFlag::Conversion(conversion) => write!(f, "{}", conversion),
+            HintFlag::Redraw(redraw) => write!(f, "{}", redraw),
+        }
+    }
+}
+
+impl FromStr for HintFlag {
+    type Err = RenderHintError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(HintFlag::BitDepth)
+            .or_else(|_| s.parse().map(HintFlag::Conversion))
+            .or_else(|_| s.parse().map(HintFlag::Redraw))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderHint {
+    pub bit_depth: PureBitDepth,
+    pub conversion: PureConversion,
+    pub redraw: PureRedraw,
+}
+
+impl fmt::Display for RenderHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|{}|{}", self.bit_depth, self.conversion, self.redraw)
+    }
+}
+
+// Strict, see RenderHint::parse
+impl FromStr for RenderHint {
+    type Err = RenderHintError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RenderHint::parse(s, false)
+    }
+}
+
+impl RenderHint {
+    // Flags can come in any order, but every part is needed once. Lenient only skips
+    // (and logs) flags it doesn't know, it never guesses a missing part
+    pub fn parse(s: &str, lenient: bool) -> Result<Self, RenderHintError> {
+        debug!("RenderHint parse received: {}", s);
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(RenderHintError::Empty);
+        }
+
+        let mut bit_depth = None;
+        let mut conversion = None;
+        let mut redraw = None;
+        for part in s.split('|').map(str::trim) {
+            let flag = match part.parse::<HintFlag>() {
+                Ok(flag) => flag,
+                Err(e) if lenient => {
+                    warn!("Ignoring render hint flag {:?} in {:?}: {}", part, s, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let previous = match flag {
+                HintFlag::BitDepth(b) => bit_depth.replace(b).map(HintFlag::BitDepth),
+                HintFlag::Conversion(c) => conversion.replace(c).map(HintFlag::Conversion),
+                HintFlag::Redraw(r) => redraw.replace(r).map(HintFlag::Redraw),
+            };
+            if let Some(previous) = previous {
+                return Err(RenderHintError::Duplicate(previous, flag));
+            }
+        }
+
+        Ok(RenderHint {
+            bit_depth: bit_depth.ok_or(RenderHintError::MissingBitDepth)?,
+            conversion: conversion.ok_or(RenderHintError::MissingConversion)?,
+            redraw: redraw.ok_or(RenderHintError::MissingRedraw)?,
+        })
+    }
+
+    // What the driver uses now, unknown flags from a newer driver are only logged
+    pub async fn get_render_hint() -> io::Result<Self> {
+        let value = get_ebc_property("DefaultHintHr").await?;
+        RenderHint::parse(&value, true).map_err(io::Error::other)
+    }
+
+    pub async fn set(&self) -> std::io::Result<()> {
+        let line = format!(
+            "busctl --user set-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 DefaultHintHr s {}",
+            self
+        );
+        try_run_cmd(&line).await?;
+        Ok(())
+    }
+}
+
+// Reads a property of the ebc dbus service, busctl prints the signature then the value, like: s "Y2|T|r"
+pub async fn get_ebc_property(name: &str) -> io::Result<String> {
+    let out = try_run_cmd(&format!(
+        "busctl --user get-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 {}",
+        name
+    ))
+    .await?;
+    out.trim()
+        .split_once(' ')
+        .map(|(_signature, value)| value.trim_matches('"').to_string())
+        .ok_or_else(|| io::Error::other(format!("Unexpected busctl output for {}: {}", name, out)))
+}
+
+pub async fn get_ebc_u8(name: &str) -> io::Result<u8> {
+    let value = get_ebc_property(name).await?;
+    value
+        .parse()
+        .map_err(|_| io::Error::other(format!("{} is not a number: {}", name, value)))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PureDriverMode {
+    Normal, // 0
+    Fast,   // 1
+}
+
+impl PureDriverMode {
+    pub async fn set(&self) -> io::Result<()> {
+        let value = match self {
+            PureDriverMode::Normal => 0,
+            PureDriverMode::Fast => 1,
+        };
+        try_run_cmd(&format!(
+            "busctl --user set-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 DriverMode y {}",
+            value
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get() -> io::Result<Self> {
+        match get_ebc_u8("DriverMode").await? {
+            0 => Ok(PureDriverMode::Normal),
+            1 => Ok(PureDriverMode::Fast),
+            other => Err(io::Error::other(format!(
+                "Unsupported driver mode {}",
+                other
+            ))),
+        }
+    }
+}
+
+// What the driver holds for a DriverMode, None is for values the mode does not use
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DriverState {
+    pub driver_mode: PureDriverMode,
+    pub dither_mode: Option<Dithering>,
+    pub hint: Option<RenderHint>,
+    pub redraw_delay: Option<u16>,
+    pub y1_threshold: ThresholdLevel,
+}
+
+impl DriverState {
+    pub fn from_driver_mode(mode: &DriverMode) -> Self {
+        let mut state = DriverState {
+            driver_mode: PureDriverMode::Normal,
+            dither_mode: None,
+            hint: None,
+            redraw_delay: None,
+            // Reset when not used, so the next Y1 window starts from the default
+            y1_threshold: ThresholdLevel::default(),
+        };
+        let (bit_depth, conversion, redraw) = match mode {
+            DriverMode::Fast(dithering) => {
+                state.driver_mode = PureDriverMode::Fast;
+                state.dither_mode = Some(*dithering);
+                return state;
+            }
+            DriverMode::Normal(BitDepth::Y1(conversion, level)) => {
+                if *conversion == Conversion::Thresholding {
+                    state.y1_threshold = *level;
+                }
+                // Fast drawing doesn't make sense in Y1
+                (PureBitDepth::Y1, *conversion, Redraw::DisableFastDrawing)
+            }
+            DriverMode::Normal(BitDepth::Y2(conversion, redraw)) => {
+                (PureBitDepth::Y2, *conversion, *redraw)
+            }
+            // Y4 has nothing to convert
+            DriverMode::Normal(BitDepth::Y4(redraw)) => {
+                (PureBitDepth::Y4, Conversion::Thresholding, *redraw)
+            }
+        };
+        let conversion = match conversion {
+            Conversion::Thresholding => PureConversion::Thresholding,
+            Conversion::Dithering(dithering) => {
+                state.dither_mode = Some(dithering);
+                PureConversion::Dithering
+            }
+        };
+        let redraw = match redraw {
+            Redraw::FastDrawing(options) => {
+                state.redraw_delay = Some(options.delay);
+                PureRedraw::FastDrawing
+            }
+            Redraw::DisableFastDrawing => PureRedraw::DisableFastDrawing,
+        };
+        state.hint = Some(RenderHint {
+            bit_depth,
+            conversion,
+            redraw,
+        });
+        state
+    }
+
+    // Back to the tree, what the GUI and configs use. Values the driver doesn't use for a
+    // mode are not kept by from_driver_mode, so the round trip only normalizes them
+    // (like the level of a dithered Y1). None if the state has no hint in normal mode
+    pub fn to_driver_mode(&self) -> Option<DriverMode> {
+        let dithering = self.dither_mode.unwrap_or_default();
+        let hint = match self.driver_mode {
+            PureDriverMode::Fast => return Some(DriverMode::Fast(dithering)),
+            PureDriverMode::Normal => self.hint?,
+        };
+        let conversion = match hint.conversion {
+            PureConversion::Thresholding => Conversion::Thresholding,
+            PureConversion::Dithering => Conversion::Dithering(dithering),
+        };
+        let redraw = match hint.redraw {
+            PureRedraw::FastDrawing => Redraw::FastDrawing(
+                self.redraw_delay
+                    .map(|delay| RedrawOptions { delay })
+                    .unwrap_or_default(),
+            ),
+            PureRedraw::DisableFastDrawing => Redraw::DisableFastDrawing,
+        };
+        let bit_depth = match hint.bit_depth {
+            PureBitDepth::Y1 => BitDepth::Y1(conversion, self.y1_threshold),
+            PureBitDepth::Y2 => BitDepth::Y2(conversion, redraw),
+            PureBitDepth::Y4 => BitDepth::Y4(redraw),
+        };
+        Some(DriverMode::Normal(bit_depth))
+    }
+
+    // A window's state with the driver wide values it uses taken from the driver, like a
+    // threshold changed from the panel. Its render hint is kept, the compositor sets that one
+    pub fn with_globals_from(&self, current: &DriverState) -> DriverState {
+        if current.driver_mode == PureDriverMode::Fast {
+            return DriverState {
+                driver_mode: PureDriverMode::Fast,
+                dither_mode: current.dither_mode.or(self.dither_mode),
+                hint: None,
+                redraw_delay: None,
+                y1_threshold: ThresholdLevel::default(),
+            };
+        }
+        let Some(hint) = self.hint.or(current.hint) else {
+            return *self;
+        };
+        let dithered = hint.conversion == PureConversion::Dithering;
+        let thresholded = hint.bit_depth == PureBitDepth::Y1 && !dithered;
+        DriverState {
+            driver_mode: PureDriverMode::Normal,
+            dither_mode: dithered
+                .then(|| current.dither_mode.or(self.dither_mode))
+                .flatten(),
+            hint: Some(hint),
+            redraw_delay: (hint.redraw == PureRedraw::FastDrawing)
+                .then(|| current.redraw_delay.or(self.redraw_delay))
+                .flatten(),
+            y1_threshold: if thresholded {
+                current.y1_threshold
+            } else {
+                ThresholdLevel::default()
+            },
+        }
+    }
+
+    // Everything is read, so nothing is None
+    pub async fn read() -> io::Result<Self> {
+        Ok(DriverState {
+            driver_mode: PureDriverMode::get().await?,
+            dither_mode: Some(read_dither_mode().await?),
+            hint: Some(RenderHint::get_render_hint().await?),
+            redraw_delay: Some(read_redraw_delay().await?),
+            y1_threshold: ThresholdLevel::get().await?,
+        })
+    }
+}
+
+impl DriverMode {
+    // What the driver runs now, built from the same values a plan writes
+    pub async fn get() -> io::Result<Self> {
+        DriverState::read()
+            .await?
+            .to_driver_mode()
+            .ok_or_else(|| io::Error::other("Driver state has no render hint"))
+    }
+}
+
+pub async fn read_dither_mode() -> io::Result<Dithering> {
+    let value = get_ebc_u8("DitherMode").await?;
+    Dithering::from_u8(value)
+        .ok_or_else(|| io::Error::other(format!("Unknown dither mode {}", value)))
+}
+
+pub async fn read_redraw_delay() -> io::Result<u16> {
+    let value = get_ebc_property("RedrawDelay").await?;
+    value
+        .parse()
+        .map_err(|_| io::Error::other(format!("Bad redraw delay {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIT_DEPTHS: [PureBitDepth; 3] = [PureBitDepth::Y1, PureBitDepth::Y2, PureBitDepth::Y4];
+    const CONVERSIONS: [PureConversion; 2] =
+        [PureConversion::Thresholding, PureConversion::Dithering];
+    const REDRAWS: [PureRedraw; 2] = [PureRedraw::FastDrawing, PureRedraw::DisableFastDrawing];
+    const DITHERINGS: [Dithering; 3] = [
+        Dithering::Bayer,
+        Dithering::BlueNoise16,
+        Dithering::BlueNoise32,
+    ];
+
+    fn all_hints() -> Vec<RenderHint> {
+        let mut hints = Vec::new();
+        for bit_depth in BIT_DEPTHS {
+            for conversion in CONVERSIONS {
+                for redraw in REDRAWS {
+                    hints.push(RenderHint {
+                        bit_depth,
+                        conversion,
+                        redraw,
+                    });
+                }
+            }
+        }
+        hints
+    }
+
+    // Y1 never fast draws and Y4 has nothing to convert, the rest is kept as is
+    fn driver_can_hold(hint: &RenderHint) -> bool {
+        match hint.bit_depth {
+            PureBitDepth::Y1 => hint.redraw == PureRedraw::DisableFastDrawing,
+            PureBitDepth::Y2 => true,
+            PureBitDepth::Y4 => hint.conversion == PureConversion::Thresholding,
+        }
+    }
+
+    fn normal_state(hint: RenderHint, dithering: Dithering) -> DriverState {
+        DriverState {
+            driver_mode: PureDriverMode::Normal,
+            dither_mode: (hint.conversion == PureConversion::Dithering).then_some(dithering),
+            hint: Some(hint),
+            redraw_delay: (hint.redraw == PureRedraw::FastDrawing).then_some(120),
+            y1_threshold: ThresholdLevel::_11,
+        }
+    }
+
+    #[test]
+    fn every_pure_combination_round_trips() {
+        for hint in all_hints() {
+            for dithering in DITHERINGS {
+                let state = normal_state(hint, dithering);
+                let mode = state.to_driver_mode().unwrap();
+                let back = DriverState::from_driver_mode(&mode);
+                if driver_can_hold(&hint) {
+                    // The threshold only survives where it's used
+                    let expected = DriverState {
+                        y1_threshold: if hint.bit_depth == PureBitDepth::Y1
+                            && hint.conversion == PureConversion::Thresholding
+                        {
+                            state.y1_threshold
+                        } else {
+                            ThresholdLevel::default()
+                        },
+                        ..state
+                    };
+                    assert_eq!(back, expected, "{}", hint);
+                }
+                // Normalized once, stable from then on
+                let again = DriverState::from_driver_mode(&back.to_driver_mode().unwrap());
+                assert_eq!(again, back, "{}", hint);
+            }
+        }
+    }
+
+    #[test]
+    fn fast_mode_round_trips() {
+        for dithering in DITHERINGS {
+            let mode = DriverMode::Fast(dithering);
+            let state = DriverState::from_driver_mode(&mode);
+            assert_eq!(state.driver_mode, PureDriverMode::Fast);
+            assert_eq!(state.hint, None);
+            assert_eq!(state.to_driver_mode(), Some(mode));
+        }
+    }
+
+    #[test]
+    fn normal_mode_without_hint_has_no_tree() {
+        let state = DriverState {
+            hint: None,
+            ..DriverState::from_driver_mode(&DriverMode::default())
+        };
+        assert_eq!(state.to_driver_mode(), None);
+    }
+
+    fn read_state(driver_mode: PureDriverMode, hint: &str) -> DriverState {
+        DriverState {
+            driver_mode,
+            dither_mode: Some(Dithering::BlueNoise32),
+            hint: Some(hint.parse().unwrap()),
+            redraw_delay: Some(200),
+            y1_threshold: ThresholdLevel::_12,
+        }
+    }
+
+    #[test]
+    fn window_takes_the_globals_it_uses() {
+        let current = read_state(PureDriverMode::Normal, "Y2|T|r");
+        let with_globals = |mode: DriverMode| {
+            DriverState::from_driver_mode(&mode)
+                .with_globals_from(&current)
+                .to_driver_mode()
+                .unwrap()
+        };
+        assert_eq!(
+            with_globals(DriverMode::Normal(BitDepth::Y1(
+                Conversion::Thresholding,
+                ThresholdLevel::_5
+            ))),
+            DriverMode::Normal(BitDepth::Y1(Conversion::Thresholding, ThresholdLevel::_12))
+        );
+        assert_eq!(
+            with_globals(DriverMode::Normal(BitDepth::Y2(
+                Conversion::Dithering(Dithering::Bayer),
+                Redraw::FastDrawing(RedrawOptions { delay: 50 })
+            ))),
+            DriverMode::Normal(BitDepth::Y2(
+                Conversion::Dithering(Dithering::BlueNoise32),
+                Redraw::FastDrawing(RedrawOptions { delay: 200 })
+            ))
+        );
+        // Nothing driver wide is used, so nothing changes
+        let plain = DriverMode::Normal(BitDepth::Y4(Redraw::DisableFastDrawing));
+        assert_eq!(with_globals(plain), plain);
+    }
+
+    #[test]
+    fn window_follows_the_driver_mode() {
+        let fast = read_state(PureDriverMode::Fast, "Y2|T|r");
+        let state = DriverState::from_driver_mode(&DriverMode::default());
+        assert_eq!(
+            state.with_globals_from(&fast).to_driver_mode(),
+            Some(DriverMode::Fast(Dithering::BlueNoise32))
+        );
+        // Back from fast there is no hint of the window's own, the driver's is used
+        let normal = read_state(PureDriverMode::Normal, "Y4|T|R");
+        let state = DriverState::from_driver_mode(&DriverMode::Fast(Dithering::Bayer));
+        assert_eq!(
+            state.with_globals_from(&normal).to_driver_mode(),
+            Some(DriverMode::Normal(BitDepth::Y4(Redraw::FastDrawing(
+                RedrawOptions { delay: 200 }
+            ))))
+        );
+    }
+
+    #[test]
+    fn render_hint_display_parses_back() {
+        for hint in all_hints() {
+            let text = hint.to_string();
+            assert_eq!(RenderHint::parse(&text, false), Ok(hint));
+            assert_eq!(RenderHint::parse(&text, true), Ok(hint));
+            assert_eq!(text.parse::<RenderHint>(), Ok(hint));
+        }
+    }
+
+    #[test]
+    fn render_hint_flags_in_any_order() {
+        let hint = RenderHint::parse(" r | T |Y4", false).unwrap();
+        assert_eq!(hint.to_string(), "Y4|T|r");
+    }
+
+    #[test]
+    fn strict_rejects_unknown_flags() {
+        assert_eq!(
+            RenderHint::parse("Y2|T|r|X", false),
+            Err(RenderHintError::UnknownFlag("X".to_string()))
+        );
+        assert_eq!(
+            RenderHint::parse("Y3|T|r", false),
+            Err(RenderHintError::UnknownFlag("Y3".to_string()))
+        );
+    }
+
+    #[test]
+    fn lenient_skips_unknown_flags() {
+        let expected = RenderHint {
+            bit_depth: PureBitDepth::Y2,
+            conversion: PureConversion::Thresholding,
+            redraw: PureRedraw::DisableFastDrawing,
+        };
+        assert_eq!(RenderHint::parse("Y2|X|T|r", true), Ok(expected));
+        assert_eq!(RenderHint::parse("Y2|T|r|", true), Ok(expected));
+        // Skipping never fills in a missing part
+        assert_eq!(
+            RenderHint::parse("Y2|X|r", true),
+            Err(RenderHintError::MissingConversion)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(RenderHint::parse("  ", false), Err(RenderHintError::Empty));
+        assert_eq!(
+            RenderHint::parse("Y1|Y2|T|r", true),
+            Err(RenderHintError::Duplicate(
+                HintFlag::BitDepth(PureBitDepth::Y1),
+                HintFlag::BitDepth(PureBitDepth::Y2)
+            ))
+        );
+        assert_eq!(
+            RenderHint::parse("T|r", false),
+            Err(RenderHintError::MissingBitDepth)
+        );
+        assert_eq!(
+            RenderHint::parse("Y2|T", false),
+            Err(RenderHintError::MissingRedraw)
+        );
+    }
+}
@@ -4,6 +4,8 @@ use log::{debug, error};
 use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 
+pub mod driver;
+
 // The mess of connected enums is so we know what affects when, so:
 // - We can set only what's needed
 // - We show only what can be changed
@@ -96,6 +98,8 @@ impl std::fmt::Display for Conversion {
     }
 }
 
+const Y1_THRESHOLD_PATH: &str = "/sys/module/rockchip_ebc_blit_neon/parameters/y4_threshold_y1";
+
 // So the configurator works well...
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Gui, Default, Serialize, Deserialize)]
@@ -118,7 +122,7 @@ pub enum ThresholdLevel {
 }
 
 impl ThresholdLevel {
-    pub async fn set(&self) {
+    pub async fn set(&self) -> std::io::Result<()> {
         /*
         if level < 2 || level > 15 {
             error!("Wrong treshold level");
@@ -127,19 +131,19 @@ impl ThresholdLevel {
 
         let level: u8 = self.to_u8();
         debug!("Writing Y1 threshold level: {}", level);
-        if let Err(e) = tokio::fs::write(
-            "/sys/module/rockchip_ebc_blit_neon/parameters/y4_threshold_y1",
-            level.to_string(),
-        )
-        .await
-        {
-            error!("Failed to set threshold: {}", e);
-        }
+        tokio::fs::write(Y1_THRESHOLD_PATH, level.to_string())
+            .await
+            .inspect_err(|e| error!("Failed to set threshold: {}", e))
     }
 
-    pub fn get_from_eww(level: u8) -> Self {
-        let converted = 2 + ((level.saturating_sub(1) as f32 / 99.0) * 13.0).round() as u8;
-        ThresholdLevel::try_from(converted).unwrap()
+    pub async fn get() -> std::io::Result<Self> {
+        let level = tokio::fs::read_to_string(Y1_THRESHOLD_PATH).await?;
+        level
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .and_then(|level| ThresholdLevel::try_from(level).ok())
+            .ok_or_else(|| std::io::Error::other(format!("Bad threshold level: {}", level)))
     }
 
     pub async fn set_eww_number(&self) {
@@ -230,8 +234,9 @@ pub struct RedrawOptions {
 }
 
 impl RedrawOptions {
-    pub async fn set(&self) {
-        run_cmd(&format!("busctl --user set-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 RedrawDelay q {}", self.delay)).await;
+    pub async fn set(&self) -> std::io::Result<()> {
+        try_run_cmd(&format!("busctl --user set-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 RedrawDelay q {}", self.delay)).await?;
+        Ok(())
     }
 }
 
@@ -263,7 +268,7 @@ impl DriverMode {
 }
 
 impl Dithering {
-    pub async fn set(&self) {
+    pub async fn set(&self) -> std::io::Result<()> {
         let string = match self {
             Dithering::Bayer => "0",
             Dithering::BlueNoise16 => "1",
@@ -273,7 +278,17 @@ impl Dithering {
             "busctl --user set-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 DitherMode y {}",
             string
         );
-        run_cmd(&line).await;
+        try_run_cmd(&line).await?;
+        Ok(())
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Dithering::Bayer),
+            1 => Some(Dithering::BlueNoise16),
+            2 => Some(Dithering::BlueNoise32),
+            _ => None,
+        }
     }
 }
 
@@ -288,18 +303,86 @@ pub async fn run_cmd(line: &str) -> String {
     String::from_utf8_lossy(&out.stdout).into_owned()
 }
 
+// Like run_cmd, but fails when the command can't run or exits with an error
+pub async fn try_run_cmd(line: &str) -> std::io::Result<String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    debug!("Running try_run_cmd as: {} {:?}", parts[0], &parts[1..]);
+    let out = Command::new(parts[0]).args(&parts[1..]).output().await?;
+    if !out.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{} exited with {}: {}",
+            parts[0],
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
 #[derive(Clone, Debug, PartialEq, Gui, Default, Serialize, Deserialize)]
 pub struct EinkWindowSetting {
     pub app_id: String,
     pub settings: DriverMode,
+    #[serde(default)]
+    #[enum2egui(label = "Gamma")]
+    pub gamma: GammaLevel,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Gui, Serialize, Deserialize)]
+pub struct GammaLevel {
+    #[enum2egui(label = "Gamma in tenths, 10 changes nothing (1-100)")]
+    pub tenths: u8,
+}
+
+impl Default for GammaLevel {
+    fn default() -> Self {
+        Self { tenths: 10 }
+    }
+}
+
+// When to do a global refresh to clean up ghosting, 0 turns a trigger off
+#[derive(Copy, Clone, Debug, PartialEq, Gui, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RefreshPolicy {
+    #[enum2egui(label = "Refresh after this many partial updates (0 is off)")]
+    pub after_updates: u32,
+    #[enum2egui(label = "Refresh after this many seconds without input (0 is off)")]
+    pub idle_secs: u32,
+    #[enum2egui(label = "Refresh when going from fast to normal mode")]
+    pub on_fast_to_normal: bool,
+    #[enum2egui(label = "Refresh every this many seconds (0 is off)")]
+    pub interval_secs: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Gui, Default, Serialize, Deserialize)]
+pub struct EinkPreset {
+    pub name: String,
+    pub settings: DriverMode,
+    #[serde(default)]
+    #[enum2egui(label = "Ghosting cleanup")]
+    pub refresh: RefreshPolicy,
+    #[serde(default)]
+    #[enum2egui(label = "Gamma")]
+    pub gamma: GammaLevel,
 }
 
 static DEFAULT_WINDOW_SETTINGS: &str =
     include_str!("../../eink-window-settings/other/default/config.ron");
+static DEFAULT_PRESETS: &str = include_str!("../../eink-window-settings/other/default/presets.ron");
 pub const WINDOW_SETTINGS_HOME_CONFIG_DIR: &str = "/.config/eink-window-settings/";
 pub const WINDOW_SETTINGS_CONFIG_NAME: &str = "config.ron";
+pub const PRESETS_CONFIG_NAME: &str = "presets.ron";
+
+// Full path of a file in the eink-window-settings config dir of the current user
+pub fn home_config_path(name: &str) -> String {
+    let username = std::env::var("USER").unwrap_or_default();
+    format!(
+        "/home/{}{}{}",
+        username, WINDOW_SETTINGS_HOME_CONFIG_DIR, name
+    )
+}
 
-pub fn load_window_settings(path: String) -> Vec<EinkWindowSetting> {
+fn load_ron_config<T: serde::de::DeserializeOwned>(path: String, default: &str) -> T {
     if let Some(parent) = std::path::Path::new(&path).parent() {
         std::fs::create_dir_all(parent)
             .unwrap_or_else(|e| panic!("Failed to create directories for {}: {}", path, e));
@@ -312,9 +395,9 @@ pub fn load_window_settings(path: String) -> Vec<EinkWindowSetting> {
                 "File {} not found or unreadable. Creating default settings.",
                 path
             );
-            std::fs::write(&path, DEFAULT_WINDOW_SETTINGS)
+            std::fs::write(&path, default)
                 .unwrap_or_else(|e| panic!("Failed to write default settings to {}: {}", path, e));
-            DEFAULT_WINDOW_SETTINGS.to_string()
+            default.to_string()
         }
     };
 
@@ -325,12 +408,174 @@ pub fn load_window_settings(path: String) -> Vec<EinkWindowSetting> {
                 "Failed to parse settings from file. Rewriting with default settings at {}",
                 path
             );
-            std::fs::write(&path, DEFAULT_WINDOW_SETTINGS)
+            std::fs::write(&path, default)
                 .unwrap_or_else(|e| panic!("Failed to write default settings to {}: {}", path, e));
-            ron::from_str(DEFAULT_WINDOW_SETTINGS)
+            ron::from_str(default)
                 .unwrap_or_else(|e| panic!("Failed to parse default settings: {}", e))
         }
     }
 }
 
+pub fn load_window_settings(path: String) -> Vec<EinkWindowSetting> {
+    load_ron_config(path, DEFAULT_WINDOW_SETTINGS)
+}
+
+pub fn load_presets(path: String) -> Vec<EinkPreset> {
+    load_ron_config(path, DEFAULT_PRESETS)
+}
+
+// Never writes, unlike load_ron_config, so a file the GUI is saving or a typo isn't replaced
+// with the defaults. A missing file is the default
+fn read_ron_config<T: serde::de::DeserializeOwned>(
+    path: &str,
+    default: &str,
+) -> std::io::Result<T> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => default.to_string(),
+        Err(e) => return Err(e),
+    };
+    ron::from_str(&contents).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to parse {}: {}", path, e),
+        )
+    })
+}
+
+pub fn read_window_settings(path: &str) -> std::io::Result<Vec<EinkWindowSetting>> {
+    read_ron_config(path, DEFAULT_WINDOW_SETTINGS)
+}
+
+// Written next to it and moved over, so a reader never sees half a file
+pub fn write_window_settings(path: &str, settings: &[EinkWindowSetting]) -> std::io::Result<()> {
+    let contents = ron::ser::to_string_pretty(settings, ron::ser::PrettyConfig::default())
+        .map_err(std::io::Error::other)?;
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = format!("{}.tmp", path);
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)
+}
+
+pub fn read_presets(path: &str) -> std::io::Result<Vec<EinkPreset>> {
+    read_ron_config(path, DEFAULT_PRESETS)
+}
+
+// Maps a slider position to a real value and back, both ranges inclusive. Whichever side
+// has fewer steps round trips exactly, so by default the slider covers the value range
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SliderMapping {
+    pub slider_min: i32,
+    pub slider_max: i32,
+    pub min: i32,
+    pub max: i32,
+    pub unit: String,
+}
+
+impl SliderMapping {
+    pub fn new(min: i32, max: i32, unit: &str) -> Self {
+        Self {
+            slider_min: min,
+            slider_max: max,
+            min,
+            max,
+            unit: unit.to_string(),
+        }
+    }
+
+    pub fn with_slider(mut self, slider_min: i32, slider_max: i32) -> Self {
+        self.slider_min = slider_min;
+        self.slider_max = slider_max;
+        self
+    }
+
+    pub fn to_value(&self, slider: i32) -> i32 {
+        scale(
+            slider,
+            (self.slider_min, self.slider_max),
+            (self.min, self.max),
+        )
+    }
+
+    pub fn to_slider(&self, value: i32) -> i32 {
+        scale(
+            value,
+            (self.min, self.max),
+            (self.slider_min, self.slider_max),
+        )
+    }
+}
+
+// Linear, rounded to the nearest step. Out of range input is clamped
+fn scale(x: i32, from: (i32, i32), to: (i32, i32)) -> i32 {
+    let from_span = from.1 as f64 - from.0 as f64;
+    if from_span == 0.0 {
+        return to.0;
+    }
+    let x = x.clamp(from.0.min(from.1), from.0.max(from.1));
+    let scaled = (x as f64 - from.0 as f64) / from_span * (to.1 as f64 - to.0 as f64);
+    (to.0 as f64 + scaled.round()) as i32
+}
+
 pub const PINENOTE_ENABLE_SOCKET: &str = "/tmp/ps_quill_niri.sock";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_mapping() {
+        let mapping = SliderMapping::new(0, 100, "%");
+        for x in 0..=100 {
+            assert_eq!(mapping.to_value(x), x);
+            assert_eq!(mapping.to_slider(x), x);
+        }
+    }
+
+    #[test]
+    fn fewer_values_round_trip_exactly() {
+        let threshold = SliderMapping::new(2, 15, "").with_slider(1, 100);
+        for value in 2..=15 {
+            assert_eq!(threshold.to_value(threshold.to_slider(value)), value);
+        }
+        let redraw = SliderMapping::new(10, 300, "ms").with_slider(1, 100);
+        for slider in 1..=100 {
+            assert_eq!(redraw.to_slider(redraw.to_value(slider)), slider);
+        }
+    }
+
+    #[test]
+    fn matches_the_old_eww_conversion() {
+        let threshold = SliderMapping::new(2, 15, "").with_slider(1, 100);
+        let redraw = SliderMapping::new(10, 300, "ms").with_slider(1, 100);
+        for slider in 1..=100 {
+            let level = 2 + ((slider - 1) as f32 / 99.0 * 13.0).round() as i32;
+            assert_eq!(threshold.to_value(slider), level);
+            let delay = ((slider - 1) as f32 / 99.0 * 290.0 + 10.0).round() as i32;
+            assert_eq!(redraw.to_value(slider), delay);
+        }
+    }
+
+    #[test]
+    fn out_of_range_is_clamped() {
+        let mapping = SliderMapping::new(10, 300, "ms").with_slider(1, 100);
+        assert_eq!(mapping.to_value(-5), 10);
+        assert_eq!(mapping.to_value(1000), 300);
+        assert_eq!(mapping.to_slider(0), 1);
+        assert_eq!(mapping.to_slider(i32::MAX), 100);
+    }
+
+    #[test]
+    fn reversed_and_empty_ranges() {
+        let reversed = SliderMapping::new(100, 0, "").with_slider(0, 100);
+        assert_eq!(reversed.to_value(0), 100);
+        assert_eq!(reversed.to_value(25), 75);
+        assert_eq!(reversed.to_slider(75), 25);
+        assert_eq!(reversed.to_value(150), 0);
+        let empty = SliderMapping::new(5, 5, "").with_slider(0, 100);
+        assert_eq!(empty.to_value(42), 5);
+        assert_eq!(empty.to_slider(5), 0);
+    }
+}
@@ -0,0 +1,468 @@
+// Frontlight brightness following the ambient light sensor. The curve from illuminance to
+// brightness starts from the config and learns from every manual brightness change while it's on
+
+use std::{path::PathBuf, time::Duration};
+
+use enums::{FrontlightChange, Requests};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
+
+use crate::backlight::{BacklightControl, Frontlight, read_channels};
+use crate::config::state_path;
+
+pub const CURVE_NAME: &str = "brightness_curve.ron";
+// Smaller differences aren't worth a change of the light
+const MIN_CHANGE: u8 = 2;
+// A manual change holds until the light changes by this factor
+const HOLD_FACTOR: f64 = 2.0;
+// How far a correction reaches along the curve, in natural log of lux
+const LEARN_WIDTH: f64 = 1.0;
+
+// Lux and brightness in percent
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub lux: f64,
+    pub brightness: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoBrightnessConfig {
+    // On at startup, it's toggled with requests
+    pub enabled: bool,
+    // Searched for a device with in_illuminance_input or in_illuminance_raw
+    pub iio_dir: String,
+    pub poll_ms: u32,
+    pub curve: Vec<CurvePoint>,
+}
+
+impl Default for AutoBrightnessConfig {
+    fn default() -> Self {
+        let point = |lux, brightness| CurvePoint { lux, brightness };
+        Self {
+            enabled: false,
+            iio_dir: "/sys/bus/iio/devices".to_string(),
+            poll_ms: 2000,
+            // Bright daylight needs no frontlight on e-ink
+            curve: vec![
+                point(0.0, 10.0),
+                point(10.0, 30.0),
+                point(100.0, 50.0),
+                point(1000.0, 20.0),
+                point(10000.0, 0.0),
+            ],
+        }
+    }
+}
+
+// Interpolated on the log of the illuminance, that's closer to how bright it looks
+pub fn curve_brightness(curve: &[CurvePoint], lux: f64) -> Option<f64> {
+    let x = log_lux(lux);
+    let first = curve.first()?;
+    let last = curve.last()?;
+    if x <= log_lux(first.lux) {
+        return Some(first.brightness);
+    }
+    if x >= log_lux(last.lux) {
+        return Some(last.brightness);
+    }
+    curve.windows(2).find_map(|pair| {
+        let (a, b) = (log_lux(pair[0].lux), log_lux(pair[1].lux));
+        (x >= a && x <= b).then(|| {
+            let progress = if b > a { (x - a) / (b - a) } else { 1.0 };
+            pair[0].brightness + (pair[1].brightness - pair[0].brightness) * progress
+        })
+    })
+}
+
+// Pulls the curve toward the brightness the user picked, most at the current illuminance
+// and less the further a point is from it
+pub fn learn(curve: &mut [CurvePoint], lux: f64, brightness: f64) {
+    let Some(current) = curve_brightness(curve, lux) else {
+        return;
+    };
+    let error = brightness - current;
+    let x = log_lux(lux);
+    for point in curve.iter_mut() {
+        let distance = (log_lux(point.lux) - x) / LEARN_WIDTH;
+        let weight = (-distance * distance / 2.0).exp();
+        point.brightness = (point.brightness + error * weight).clamp(0.0, 100.0);
+    }
+}
+
+// Learned from. A channel of its own changes the brightness too, with the temperature
+fn changes_brightness(request: &Requests) -> bool {
+    matches!(
+        request,
+        Requests::SetBacklight(..)
+            | Requests::SetFrontlight(
+                FrontlightChange::Brightness(_) | FrontlightChange::BrightnessStep(_)
+            )
+    )
+}
+
+fn log_lux(lux: f64) -> f64 {
+    lux.max(0.0).ln_1p()
+}
+
+// In lux, from the first device in the iio directory with an illuminance channel
+pub async fn read_illuminance(iio_dir: &str) -> std::io::Result<f64> {
+    let mut entries = tokio::fs::read_dir(iio_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let read = |name: &str| {
+            let path = entry.path().join(name);
+            async move {
+                let value = tokio::fs::read_to_string(&path).await.ok()?;
+                value.trim().parse::<f64>().ok()
+            }
+        };
+        if let Some(lux) = read("in_illuminance_input").await {
+            return Ok(lux);
+        }
+        if let Some(raw) = read("in_illuminance_raw").await {
+            let scale = read("in_illuminance_scale").await.unwrap_or(1.0);
+            let offset = read("in_illuminance_offset").await.unwrap_or(0.0);
+            return Ok((raw + offset) * scale);
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "no illuminance sensor",
+    ))
+}
+
+pub struct AutoBrightness {
+    pub config: AutoBrightnessConfig,
+    pub channel_rx: broadcast::Receiver<Requests>,
+    pub frontlight_tx: mpsc::Sender<BacklightControl>,
+    pub enabled: bool,
+    pub curve: Vec<CurvePoint>,
+    // In percent
+    pub last_set: Option<u8>,
+    // The illuminance at the last manual change, held until it changes enough
+    pub hold_lux: Option<f64>,
+    // To read the brightness the user picked once the fade is done
+    pub fade: Duration,
+}
+
+impl AutoBrightness {
+    pub async fn start(&mut self) {
+        info!("Starting AutoBrightness");
+        self.curve = self.load_curve();
+        loop {
+            if self.enabled {
+                self.adjust().await;
+            }
+            tokio::select! {
+                _ = sleep(Duration::from_millis(self.config.poll_ms.max(100) as u64)) => {}
+                res = self.channel_rx.recv() => match res {
+                    Ok(Requests::AutoBrightness(enabled)) => {
+                        info!("Auto brightness {}", if enabled { "on" } else { "off" });
+                        self.enabled = enabled;
+                        self.last_set = None;
+                        self.hold_lux = None;
+                    }
+                    Ok(request) if self.enabled && changes_brightness(&request) => {
+                        self.manual_change().await;
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    async fn adjust(&mut self) {
+        let lux = match read_illuminance(&self.config.iio_dir).await {
+            Ok(lux) => lux,
+            Err(e) => {
+                warn!(
+                    "Turning auto brightness off, can't read the light sensor: {}",
+                    e
+                );
+                self.enabled = false;
+                return;
+            }
+        };
+        if let Some(hold_lux) = self.hold_lux {
+            let ratio = (lux.max(1.0) / hold_lux.max(1.0)).max(hold_lux.max(1.0) / lux.max(1.0));
+            if ratio < HOLD_FACTOR {
+                return;
+            }
+            debug!("Light changed since the manual change, following the curve again");
+            self.hold_lux = None;
+        }
+        let Some(brightness) = curve_brightness(&self.curve, lux) else {
+            return;
+        };
+        let brightness = brightness.round().clamp(0.0, 100.0) as u8;
+        if self
+            .last_set
+            .is_some_and(|last| last.abs_diff(brightness) < MIN_CHANGE)
+        {
+            return;
+        }
+        debug!("{:.0} lux, setting the frontlight to {}%", lux, brightness);
+        self.last_set = Some(brightness);
+        if let Err(e) = self
+            .frontlight_tx
+            .send(BacklightControl::Frontlight(FrontlightChange::Brightness(
+                brightness,
+            )))
+            .await
+        {
+            error!("Failed to send the frontlight change: {}", e);
+        }
+    }
+
+    async fn manual_change(&mut self) {
+        sleep(self.fade + Duration::from_millis(100)).await;
+        let (lux, channels) = match (
+            read_illuminance(&self.config.iio_dir).await,
+            read_channels().await,
+        ) {
+            (Ok(lux), Ok(channels)) => (lux, channels),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Can't learn from the manual change: {}", e);
+                return;
+            }
+        };
+        let [(cool, _), (warm, _)] = channels;
+        let brightness = Frontlight::from_channels(cool, warm, 0.5).brightness * 100.0;
+        info!("Learning {:.0}% at {:.0} lux", brightness, lux);
+        learn(&mut self.curve, lux, brightness);
+        self.last_set = Some(brightness.round() as u8);
+        self.hold_lux = Some(lux);
+        self.save_curve();
+    }
+
+    fn load_curve(&self) -> Vec<CurvePoint> {
+        let path = state_path(CURVE_NAME);
+        let mut curve: Vec<CurvePoint> = match std::fs::read_to_string(&path) {
+            Ok(contents) => ron::from_str(&contents).unwrap_or_else(|e| {
+                error!(
+                    "Failed to parse {}, using the configured curve: {}",
+                    path, e
+                );
+                self.config.curve.clone()
+            }),
+            Err(_) => self.config.curve.clone(),
+        };
+        curve.sort_by(|a, b| a.lux.total_cmp(&b.lux));
+        curve
+    }
+
+    fn save_curve(&self) {
+        let path = PathBuf::from(state_path(CURVE_NAME));
+        if let Some(parent) = path.parent()
+            && let Err(e) = std::fs::create_dir_all(parent)
+        {
+            error!("Failed to create {:?}: {}", parent, e);
+            return;
+        }
+        match ron::ser::to_string_pretty(&self.curve, ron::ser::PrettyConfig::default()) {
+            Ok(contents) => {
+                if let Err(e) = std::fs::write(&path, contents) {
+                    error!("Failed to write {:?}: {}", path, e);
+                }
+            }
+            Err(e) => error!("Failed to serialize the brightness curve: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enums::{BacklightChange, BacklightChannel};
+
+    fn default_curve() -> Vec<CurvePoint> {
+        AutoBrightnessConfig::default().curve
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn curve_goes_through_its_points() {
+        let curve = default_curve();
+        for point in &curve {
+            assert_close(
+                curve_brightness(&curve, point.lux).unwrap(),
+                point.brightness,
+            );
+        }
+    }
+
+    #[test]
+    fn curve_interpolates_on_log_lux() {
+        let curve = default_curve();
+        // Halfway between 10 and 100 lux, on the log of lux plus one
+        let lux = (11.0f64 * 101.0).sqrt() - 1.0;
+        assert_close(curve_brightness(&curve, lux).unwrap(), 40.0);
+        let lux = (101.0f64 * 1001.0).sqrt() - 1.0;
+        assert_close(curve_brightness(&curve, lux).unwrap(), 35.0);
+        // Closer to the lower point
+        let brightness = curve_brightness(&curve, 20.0).unwrap();
+        assert!((30.0..40.0).contains(&brightness), "{}", brightness);
+    }
+
+    #[test]
+    fn curve_clamps_to_its_ends() {
+        let curve = default_curve();
+        assert_eq!(curve_brightness(&curve, -5.0), Some(10.0));
+        assert_eq!(curve_brightness(&curve, 100_000.0), Some(0.0));
+        assert_eq!(curve_brightness(&curve, f64::INFINITY), Some(0.0));
+
+        let single = [CurvePoint {
+            lux: 50.0,
+            brightness: 42.0,
+        }];
+        assert_eq!(curve_brightness(&single, 0.0), Some(42.0));
+        assert_eq!(curve_brightness(&single, 500.0), Some(42.0));
+        assert_eq!(curve_brightness(&[], 50.0), None);
+    }
+
+    #[test]
+    fn learning_pulls_the_curve_near_the_illuminance() {
+        let mut curve = default_curve();
+        learn(&mut curve, 100.0, 70.0);
+        assert_close(curve_brightness(&curve, 100.0).unwrap(), 70.0);
+        // Neighbours follow part of the way, far points hardly at all
+        assert!(curve[1].brightness > 30.0 && curve[1].brightness < 50.0);
+        assert!(curve[3].brightness > 20.0 && curve[3].brightness < 40.0);
+        assert!((curve[0].brightness - 10.0).abs() < 1.0);
+        assert!(curve[4].brightness.abs() < 0.01);
+    }
+
+    #[test]
+    fn learning_between_points_moves_the_curve_there() {
+        let mut curve = default_curve();
+        let before = curve_brightness(&curve, 300.0).unwrap();
+        learn(&mut curve, 300.0, before - 20.0);
+        let after = curve_brightness(&curve, 300.0).unwrap();
+        assert!(after < before - 10.0, "{} {}", before, after);
+        assert_eq!(curve.len(), 5);
+    }
+
+    #[test]
+    fn learning_stays_in_range() {
+        let mut curve = default_curve();
+        for _ in 0..5 {
+            learn(&mut curve, 0.0, 100.0);
+            learn(&mut curve, 10000.0, 0.0);
+        }
+        assert!(
+            curve
+                .iter()
+                .all(|point| (0.0..=100.0).contains(&point.brightness))
+        );
+        assert_eq!(curve[0].brightness, 100.0);
+        assert_eq!(curve[4].brightness, 0.0);
+
+        let mut empty = Vec::new();
+        learn(&mut empty, 100.0, 50.0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn only_brightness_changes_are_learned() {
+        for change in [
+            FrontlightChange::Brightness(40),
+            FrontlightChange::BrightnessStep(-10),
+        ] {
+            assert!(changes_brightness(&Requests::SetFrontlight(change)));
+        }
+        for change in [
+            FrontlightChange::Temperature(40),
+            FrontlightChange::TemperatureStep(10),
+        ] {
+            assert!(!changes_brightness(&Requests::SetFrontlight(change)));
+        }
+        assert!(changes_brightness(&Requests::SetBacklight(
+            BacklightChannel::Warm,
+            BacklightChange::Step(5)
+        )));
+        assert!(!changes_brightness(&Requests::AutoBrightness(true)));
+    }
+
+    // An iio directory with a device per list of files, removed when dropped
+    struct TempIio(PathBuf);
+
+    impl TempIio {
+        fn new(name: &str, devices: &[&[(&str, &str)]]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("quill-iio-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            for (i, files) in devices.iter().enumerate() {
+                let device = dir.join(format!("iio:device{}", i));
+                std::fs::create_dir_all(&device).unwrap();
+                for (file, value) in *files {
+                    std::fs::write(device.join(file), format!("{}\n", value)).unwrap();
+                }
+            }
+            std::fs::create_dir_all(&dir).unwrap();
+            TempIio(dir)
+        }
+
+        async fn read(&self) -> std::io::Result<f64> {
+            read_illuminance(self.0.to_str().unwrap()).await
+        }
+    }
+
+    impl Drop for TempIio {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_processed_illuminance() {
+        let iio = TempIio::new(
+            "input",
+            &[
+                &[("in_accel_x_raw", "12"), ("name", "accel")],
+                &[
+                    ("in_illuminance_input", "123.5"),
+                    ("in_illuminance_raw", "9"),
+                ],
+            ],
+        );
+        assert_eq!(iio.read().await.unwrap(), 123.5);
+    }
+
+    #[tokio::test]
+    async fn reads_raw_illuminance_with_scale_and_offset() {
+        let iio = TempIio::new(
+            "raw",
+            &[&[
+                ("in_illuminance_raw", "200"),
+                ("in_illuminance_scale", "0.5"),
+                ("in_illuminance_offset", "10"),
+            ]],
+        );
+        assert_eq!(iio.read().await.unwrap(), 105.0);
+
+        let iio = TempIio::new("raw-only", &[&[("in_illuminance_raw", "200")]]);
+        assert_eq!(iio.read().await.unwrap(), 200.0);
+    }
+
+    #[tokio::test]
+    async fn no_sensor_is_an_error() {
+        let iio = TempIio::new("none", &[&[("in_accel_x_raw", "12")]]);
+        assert_eq!(
+            iio.read().await.unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        // Unreadable values don't count either
+        let iio = TempIio::new("garbage", &[&[("in_illuminance_input", "bright")]]);
+        assert!(iio.read().await.is_err());
+
+        let missing = std::env::temp_dir().join("quill-iio-missing");
+        assert!(read_illuminance(missing.to_str().unwrap()).await.is_err());
+    }
+}
@@ -1,112 +1,464 @@
 use async_trait::async_trait;
+use enums::{BacklightChange, BacklightChannel, FrontlightChange, Requests};
 use log::*;
-use std::{path::PathBuf, time::Duration};
-use tokio::{
-    fs::read_to_string,
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
-    time::sleep,
-};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use tokio::{fs::read_to_string, process::Command, sync::watch, task::JoinHandle, time::sleep};
 
+use crate::config::ProviderConfig;
 use crate::listener::SocketHandler;
+use crate::uevent::UeventSubscription;
 
 const PATH_BASE: &str = "/sys/class/backlight";
+pub const CHANNELS: [&str; 2] = ["backlight_cool", "backlight_warm"];
+// Between the steps of a fade
+const FADE_STEP: Duration = Duration::from_millis(20);
 
-async fn get_brightness(path: &PathBuf) -> String {
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BacklightConfig {
+    // 0 sets the brightness right away
+    pub fade_ms: u32,
+}
+
+impl Default for BacklightConfig {
+    fn default() -> Self {
+        Self { fade_ms: 300 }
+    }
+}
+
+pub fn channel_name(channel: BacklightChannel) -> &'static str {
+    match channel {
+        BacklightChannel::Cool => "backlight_cool",
+        BacklightChannel::Warm => "backlight_warm",
+    }
+}
+
+pub async fn read_value(path: &PathBuf) -> std::io::Result<u32> {
     read_to_string(path)
-        .await
-        .map(|s| s.trim().to_string())
-        .unwrap_or_else(|_| "120".to_string())
+        .await?
+        .trim()
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
 }
 
-pub struct CoolBacklightListener;
+// The current brightness and the maximum of a channel
+pub async fn read_brightness(name: &str) -> std::io::Result<(u32, u32)> {
+    let path = PathBuf::from(PATH_BASE).join(name);
+    Ok((
+        read_value(&path.join("brightness")).await?,
+        read_value(&path.join("max_brightness")).await?,
+    ))
+}
 
-#[async_trait]
-impl SocketHandler for CoolBacklightListener {
-    const SOCKET_NAME: &'static str = "backlight_cool";
+// Through sysfs, or logind when the user can't write there
+pub async fn write_brightness(name: &str, value: u32) -> std::io::Result<()> {
+    let path = PathBuf::from(PATH_BASE).join(name).join("brightness");
+    match tokio::fs::write(&path, value.to_string()).await {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            let output = Command::new("busctl")
+                .args([
+                    "call",
+                    "org.freedesktop.login1",
+                    "/org/freedesktop/login1/session/auto",
+                    "org.freedesktop.login1.Session",
+                    "SetBrightness",
+                    "ssu",
+                    "backlight",
+                    name,
+                    &value.to_string(),
+                ])
+                .output()
+                .await?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(std::io::Error::other(
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                ))
+            }
+        }
+        result => result,
+    }
+}
 
-    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
-        info!("Starting CoolBacklightListener");
-        let mut path = PathBuf::from(PATH_BASE);
-        path.push(Self::SOCKET_NAME);
-        path.push("actual_brightness");
+// The new value, clamped to what the driver takes
+pub fn apply_change(change: BacklightChange, current: u32, max: u32) -> u32 {
+    match change {
+        BacklightChange::Absolute(value) => value.min(max),
+        BacklightChange::Step(step) => (current as i64 + step as i64).clamp(0, max as i64) as u32,
+        BacklightChange::Percent(percent) => (max as u64 * percent.min(100) as u64 / 100) as u32,
+    }
+}
 
-        let mut previous_brightness = get_brightness(&path).await;
-        self.send_unix(unix, previous_brightness.clone()).await;
+// Goes to the value in small steps, a fade of 0 jumps right there
+pub async fn fade_brightness(name: &str, from: u32, to: u32, duration: Duration) {
+    let steps = (duration.as_millis() / FADE_STEP.as_millis())
+        .min(from.abs_diff(to) as u128)
+        .max(1) as i64;
+    for step in 1..=steps {
+        let value = from as i64 + (to as i64 - from as i64) * step / steps;
+        if let Err(e) = write_brightness(name, value as u32).await {
+            error!("Failed to set the brightness of {}: {}", name, e);
+            return;
+        }
+        if step < steps {
+            sleep(duration / steps as u32).await;
+        }
+    }
+}
 
-        let mut cmd = Command::new("udevadm")
-            .arg("monitor")
-            .arg("--subsystem-match=backlight")
-            .arg("--property")
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn udevadm monitor command");
+// Brightness and color temperature, both from 0 to 1 with 0 the coolest. The cool channel is
+// at full brightness up to the middle of the temperature and fades out after it, the warm one
+// the other way around, so the middle has both at full brightness
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frontlight {
+    pub brightness: f64,
+    pub temperature: f64,
+}
 
-        let stdout = cmd.stdout.take().expect("Failed to take stdout");
-        let mut reader = BufReader::new(stdout).lines();
+impl Frontlight {
+    // Cool and warm, as fractions of their maximum
+    pub fn to_channels(self) -> (f64, f64) {
+        let brightness = self.brightness.clamp(0.0, 1.0);
+        let temperature = self.temperature.clamp(0.0, 1.0);
+        (
+            brightness * (2.0 * (1.0 - temperature)).min(1.0),
+            brightness * (2.0 * temperature).min(1.0),
+        )
+    }
 
+    // With both channels off there is no temperature, the previous one is kept
+    pub fn from_channels(cool: f64, warm: f64, previous_temperature: f64) -> Self {
+        let brightness = cool.max(warm).clamp(0.0, 1.0);
+        let temperature = if brightness <= 0.0 {
+            previous_temperature
+        } else if warm >= cool {
+            1.0 - cool / (2.0 * warm)
+        } else {
+            warm / (2.0 * cool)
+        };
+        Frontlight {
+            brightness,
+            temperature: temperature.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn apply(self, change: FrontlightChange) -> Self {
+        let percent = |value: i32| value as f64 / 100.0;
+        let mut frontlight = self;
+        match change {
+            FrontlightChange::Brightness(value) => frontlight.brightness = percent(value as i32),
+            FrontlightChange::BrightnessStep(step) => frontlight.brightness += percent(step),
+            FrontlightChange::Temperature(value) => frontlight.temperature = percent(value as i32),
+            FrontlightChange::TemperatureStep(step) => frontlight.temperature += percent(step),
+        }
+        frontlight.brightness = frontlight.brightness.clamp(0.0, 1.0);
+        frontlight.temperature = frontlight.temperature.clamp(0.0, 1.0);
+        frontlight
+    }
+}
+
+// The channels as fractions, with their maximum
+pub async fn read_channels() -> std::io::Result<[(f64, u32); 2]> {
+    let mut channels = [(0.0, 0); 2];
+    for (i, name) in CHANNELS.iter().enumerate() {
+        let (current, max) = read_brightness(name).await?;
+        channels[i] = (current as f64 / max.max(1) as f64, max);
+    }
+    Ok(channels)
+}
+
+// From the provider itself, like the schedule, which aren't the user's
+pub enum BacklightControl {
+    Frontlight(FrontlightChange),
+    // Percent of the maximum the frontlight is lowered to and kept under, None lifts it
+    Limit(Option<u8>),
+}
+
+// Handles SetBacklight and SetFrontlight requests. A new request on a channel stops its running fade
+pub struct BacklightController {
+    pub channel_rx: tokio::sync::broadcast::Receiver<Requests>,
+    pub internal_channel_rx: tokio::sync::mpsc::Receiver<BacklightControl>,
+    pub config: BacklightConfig,
+    // The target of the running fade of each channel
+    pub fades: HashMap<BacklightChannel, (u32, JoinHandle<()>)>,
+    // Kept for when the frontlight is off
+    pub temperature: f64,
+    // Percent, for the internal changes only, the user can still turn the light up
+    pub limit: Option<u8>,
+}
+
+impl BacklightController {
+    pub async fn start(&mut self) {
+        info!("Starting BacklightController");
         loop {
             tokio::select! {
-                _ = reader.next_line() => {},
-                _ = sleep(Duration::from_secs(10)) => {}
+                res = self.channel_rx.recv() => match res {
+                    Ok(Requests::SetBacklight(channel, change)) => self.set(channel, change).await,
+                    Ok(Requests::SetFrontlight(change)) => self.set_frontlight(change, false).await,
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Failed to recv: {}", e);
+                        sleep(Duration::from_secs(1)).await;
+                    }
+                },
+                Some(control) = self.internal_channel_rx.recv() => match control {
+                    BacklightControl::Frontlight(change) => self.set_frontlight(change, true).await,
+                    BacklightControl::Limit(limit) => self.set_limit(limit).await,
+                },
             }
-            sleep(Duration::from_millis(5)).await;
-            let current_brightness = get_brightness(&path).await;
-            if previous_brightness != current_brightness {
-                debug!("Sending cool brightness: {}", current_brightness);
-                let new_brightness =
-                    ((current_brightness.parse::<u16>().unwrap() * 100 / 255) as u8).to_string();
-                self.send_unix(unix, new_brightness).await;
-                previous_brightness = current_brightness;
-            } else {
-                // debug!("Backlight brightness is the same");
+        }
+    }
+
+    async fn set(&mut self, channel: BacklightChannel, change: BacklightChange) {
+        let name = channel_name(channel);
+        let (current, max) = match read_brightness(name).await {
+            Ok(brightness) => brightness,
+            Err(e) => {
+                error!("Failed to read the brightness of {}: {}", name, e);
+                return;
+            }
+        };
+        // Steps during a fade go on from where it's heading
+        let base = match self.fades.remove(&channel) {
+            Some((target, fade)) if !fade.is_finished() => {
+                fade.abort();
+                target
             }
+            _ => current,
+        };
+        let target = apply_change(change, base, max);
+        self.fade(channel, current, target);
+    }
+
+    // Both channels fade together
+    async fn set_frontlight(&mut self, change: FrontlightChange, limited: bool) {
+        let Some((base, channels)) = self.read_frontlight().await else {
+            return;
+        };
+        let mut frontlight = base.apply(change);
+        if limited && let Some(limit) = self.limit {
+            frontlight.brightness = frontlight.brightness.min(limit as f64 / 100.0);
+        }
+        self.fade_frontlight(frontlight, channels);
+    }
+
+    // Dims right away, a running fade heading above the limit is turned around
+    async fn set_limit(&mut self, limit: Option<u8>) {
+        info!("Frontlight limit: {:?}", limit);
+        self.limit = limit;
+        let Some(limit) = limit else {
+            return;
+        };
+        let Some((base, channels)) = self.read_frontlight().await else {
+            return;
+        };
+        let limit = limit as f64 / 100.0;
+        if base.brightness > limit {
+            let frontlight = Frontlight {
+                brightness: limit,
+                ..base
+            };
+            self.fade_frontlight(frontlight, channels);
         }
     }
+
+    // Where the frontlight is heading, with the channels now
+    async fn read_frontlight(&self) -> Option<(Frontlight, [(f64, u32); 2])> {
+        let channels = match read_channels().await {
+            Ok(channels) => channels,
+            Err(e) => {
+                error!("Failed to read the frontlight: {}", e);
+                return None;
+            }
+        };
+        let [(cool, cool_max), (warm, warm_max)] = channels;
+        // Steps during a fade go on from where it's heading
+        let target_of = |channel, current: f64, max: u32| {
+            self.fades
+                .get(&channel)
+                .filter(|(_, fade)| !fade.is_finished())
+                .map(|(target, _)| *target as f64 / max.max(1) as f64)
+                .unwrap_or(current)
+        };
+        let base = Frontlight::from_channels(
+            target_of(BacklightChannel::Cool, cool, cool_max),
+            target_of(BacklightChannel::Warm, warm, warm_max),
+            self.temperature,
+        );
+        Some((base, channels))
+    }
+
+    fn fade_frontlight(&mut self, frontlight: Frontlight, channels: [(f64, u32); 2]) {
+        let [(cool, cool_max), (warm, warm_max)] = channels;
+        self.temperature = frontlight.temperature;
+        let (cool_target, warm_target) = frontlight.to_channels();
+        debug!("Setting the frontlight to {:?}", frontlight);
+        for (channel, current, target, max) in [
+            (BacklightChannel::Cool, cool, cool_target, cool_max),
+            (BacklightChannel::Warm, warm, warm_target, warm_max),
+        ] {
+            let current = (current * max as f64).round() as u32;
+            self.fade(channel, current, (target * max as f64).round() as u32);
+        }
+    }
+
+    fn fade(&mut self, channel: BacklightChannel, current: u32, target: u32) {
+        let name = channel_name(channel);
+        if let Some((_, fade)) = self.fades.remove(&channel) {
+            fade.abort();
+        }
+        debug!("Setting {} from {} to {}", name, current, target);
+        let duration = Duration::from_millis(self.config.fade_ms as u64);
+        let fade = tokio::spawn(fade_brightness(name, current, target, duration));
+        self.fades.insert(channel, (target, fade));
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FrontlightInfo {
+    // Through the brightness and temperature sliders, percent by default
+    brightness: i32,
+    temperature: i32,
+    cool: i32,
+    warm: i32,
 }
 
-pub struct WarmBacklightListener;
+// Both representations of the frontlight, read again on every backlight uevent
+pub struct FrontlightListener {
+    pub uevents: UeventSubscription,
+    pub config_rx: watch::Receiver<ProviderConfig>,
+}
 
 #[async_trait]
-impl SocketHandler for WarmBacklightListener {
-    const SOCKET_NAME: &'static str = "backlight_warm";
+impl SocketHandler for FrontlightListener {
+    const SOCKET_NAME: &'static str = "frontlight";
 
     async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
-        info!("Starting WarmBacklightListener");
-        let mut path = PathBuf::from(PATH_BASE);
-        path.push(Self::SOCKET_NAME);
-        path.push("actual_brightness");
+        info!("Starting FrontlightListener");
+        let mut temperature = 0.5;
+        let mut previous_json = String::new();
+        loop {
+            match read_channels().await {
+                Ok([(cool, _), (warm, _)]) => {
+                    let frontlight = Frontlight::from_channels(cool, warm, temperature);
+                    temperature = frontlight.temperature;
+                    let sliders = self.config_rx.borrow().sliders.clone();
+                    let brightness = |value: f64| sliders.brightness.to_slider(percent(value));
+                    let info = FrontlightInfo {
+                        brightness: brightness(frontlight.brightness),
+                        temperature: sliders
+                            .temperature
+                            .to_slider(percent(frontlight.temperature)),
+                        cool: brightness(cool),
+                        warm: brightness(warm),
+                    };
+                    match serde_json::to_string(&info) {
+                        Ok(json) if json != previous_json => {
+                            debug!("Sending frontlight: {}", json);
+                            self.send_unix(unix, json.clone()).await;
+                            previous_json = json;
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to serialize the frontlight: {}", e),
+                    }
+                }
+                Err(e) => error!("Failed to read the frontlight: {}", e),
+            }
+            if self.uevents.next().await.is_none() {
+                error!("Uevents for the backlight stopped");
+                break;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+}
 
-        let mut previous_brightness = get_brightness(&path).await;
-        self.send_unix(unix, previous_brightness.clone()).await;
+fn percent(fraction: f64) -> i32 {
+    (fraction * 100.0).round() as i32
+}
 
-        let mut cmd = Command::new("udevadm")
-            .arg("monitor")
-            .arg("--subsystem-match=backlight")
-            .arg("--property")
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn udevadm monitor command");
+// Percent of a channel's maximum through the brightness slider, for the topics of each
+// channel from before the frontlight one which panels still listen on
+pub struct ChannelBrightness {
+    uevents: UeventSubscription,
+    config_rx: watch::Receiver<ProviderConfig>,
+    previous: Option<i32>,
+    // The first value is read right away, the next ones after a uevent
+    read: bool,
+}
 
-        let stdout = cmd.stdout.take().expect("Failed to take stdout");
-        let mut reader = BufReader::new(stdout).lines();
+impl ChannelBrightness {
+    pub fn new(uevents: UeventSubscription, config_rx: watch::Receiver<ProviderConfig>) -> Self {
+        ChannelBrightness {
+            uevents,
+            config_rx,
+            previous: None,
+            read: false,
+        }
+    }
 
+    // The next value which differs from the last one, None once the uevents stopped
+    async fn next(&mut self, name: &str) -> Option<i32> {
         loop {
-            tokio::select! {
-                _ = reader.next_line() => {},
-                _ = sleep(Duration::from_secs(10)) => {}
+            if self.read {
+                if self.uevents.next().await.is_none() {
+                    error!("Uevents for the backlight stopped");
+                    return None;
+                }
+                sleep(Duration::from_millis(5)).await;
             }
-            sleep(Duration::from_millis(5)).await;
-            let current_brightness = get_brightness(&path).await;
-            if previous_brightness != current_brightness {
-                debug!("Sending warm brightness: {}", current_brightness);
-                let new_brightness =
-                    ((current_brightness.parse::<u16>().unwrap() * 100 / 255) as u8).to_string();
-                self.send_unix(unix, new_brightness).await;
-                previous_brightness = current_brightness;
-            } else {
-                // debug!("Backlight brightness is the same");
+            self.read = true;
+            match read_brightness(name).await {
+                Ok((current, max)) => {
+                    let value = self
+                        .config_rx
+                        .borrow()
+                        .sliders
+                        .brightness
+                        .to_slider(percent(current as f64 / max.max(1) as f64));
+                    if self.previous != Some(value) {
+                        self.previous = Some(value);
+                        return Some(value);
+                    }
+                }
+                Err(e) => error!("Failed to read the brightness of {}: {}", name, e),
             }
         }
     }
 }
+
+pub struct CoolBacklightListener {
+    pub brightness: ChannelBrightness,
+}
+
+#[async_trait]
+impl SocketHandler for CoolBacklightListener {
+    const SOCKET_NAME: &'static str = "backlight_cool";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting CoolBacklightListener");
+        while let Some(value) = self.brightness.next(Self::SOCKET_NAME).await {
+            debug!("Sending cool brightness: {}", value);
+            self.send_unix(unix, value.to_string()).await;
+        }
+    }
+}
+
+pub struct WarmBacklightListener {
+    pub brightness: ChannelBrightness,
+}
+
+#[async_trait]
+impl SocketHandler for WarmBacklightListener {
+    const SOCKET_NAME: &'static str = "backlight_warm";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting WarmBacklightListener");
+        while let Some(value) = self.brightness.next(Self::SOCKET_NAME).await {
+            debug!("Sending warm brightness: {}", value);
+            self.send_unix(unix, value.to_string()).await;
+        }
+    }
+}
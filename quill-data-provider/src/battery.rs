@@ -1,74 +1,345 @@
 use crate::listener::SocketHandler;
+use crate::uevent::UeventSubscription;
 use async_trait::async_trait;
 use log::*;
-use std::{path::PathBuf, time::Duration};
-use tokio::{
-    fs::read_to_string,
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
-    time::sleep,
+use serde::Serialize;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
+use tokio::{fs::read_to_string, sync::watch, time::sleep};
 
-pub const BATTERY_DEVICE: &'static str = "rk817-battery";
+pub const BATTERY_DEVICE: &str = "rk817-battery";
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply/";
+// Between uevents while charging or discharging, for the estimate. Idle or full, the battery
+// is only read on uevents
+const ESTIMATE_INTERVAL: Duration = Duration::from_secs(60);
 
-async fn get_battery_info(path: &PathBuf) -> String {
-    read_to_string(path)
-        .await
-        .unwrap_or_else(|_| String::from("50"))
-        .trim()
-        .to_string()
+// Everything the battery driver tells, in plain units. A value the driver doesn't have is
+// None, one which failed to read is None with the reason in errors
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BatteryInfo {
+    // In percent
+    pub capacity: Option<u8>,
+    // Charging, Discharging, Full or Not charging
+    pub status: Option<String>,
+    pub health: Option<String>,
+    // Amperes, negative while discharging
+    pub current: Option<f64>,
+    pub voltage: Option<f64>,
+    // Watts, with the sign of the current
+    pub power: Option<f64>,
+    // Degrees Celsius
+    pub temperature: Option<f64>,
+    // Ampere hours
+    pub charge_now: Option<f64>,
+    pub charge_full: Option<f64>,
+    pub charge_full_design: Option<f64>,
+    // Watt hours
+    pub energy_now: Option<f64>,
+    // An AC or USB supply is online
+    pub charger_online: Option<bool>,
+    // Minutes, from BatteryEstimator and not the driver
+    pub time_to_empty: Option<u32>,
+    pub time_to_full: Option<u32>,
+    pub errors: Vec<String>,
 }
 
-pub struct BatteryStateListener {
-    pub channel_tx: tokio::sync::mpsc::Sender<()>,
+impl BatteryInfo {
+    // From a directory like POWER_SUPPLY_DIR
+    pub async fn read(power_supply_dir: &Path) -> Self {
+        let dir = power_supply_dir.join(BATTERY_DEVICE);
+        let mut info = BatteryInfo::default();
+        let mut reader = AttributeReader {
+            dir: &dir,
+            errors: &mut info.errors,
+        };
+
+        info.capacity = reader.parse("capacity", true).await;
+        info.status = reader.read("status", true).await;
+        info.health = reader.read("health", false).await;
+        info.current = reader.micro("current_now").await;
+        info.voltage = reader.micro("voltage_now").await;
+        info.temperature = reader
+            .parse::<f64>("temp", false)
+            .await
+            .map(|tenths| tenths / 10.0);
+        info.charge_now = reader.micro("charge_now").await;
+        info.charge_full = reader.micro("charge_full").await;
+        info.charge_full_design = reader.micro("charge_full_design").await;
+        info.energy_now = reader.micro("energy_now").await;
+        info.power = match reader.micro("power_now").await {
+            Some(power) => Some(power),
+            None => info.current.zip(info.voltage).map(|(c, v)| c * v),
+        };
+        info.charger_online = charger_online(power_supply_dir, &mut info.errors).await;
+        info
+    }
 }
 
-#[async_trait]
-impl SocketHandler for BatteryStateListener {
-    const SOCKET_NAME: &'static str = "battery_state";
+// How quickly the average follows the draw, as a time constant
+const ESTIMATE_TIME_CONSTANT: Duration = Duration::from_secs(300);
+// Samples further off the average than this factor are clamped to it
+const SPIKE_RATIO: f64 = 2.0;
+// The average needs a few samples before the estimate means anything
+const MIN_SAMPLES: u32 = 3;
 
-    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
-        info!("Starting BatteryStateListener");
-        let mut path = PathBuf::from("/sys/class/power_supply/");
-        path.push(BATTERY_DEVICE);
-        path.push("status");
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum EstimateUnit {
+    // Ampere hours with amperes
+    Charge,
+    // Watt hours with watts
+    Energy,
+}
+
+// Smooths the draw for time estimates. E-ink refreshes pull short spikes of current, which
+// would make the estimate jump around, so single samples can only move the average a little
+#[derive(Default)]
+pub struct BatteryEstimator {
+    rate: Option<f64>,
+    samples: u32,
+    last_sample: Option<Instant>,
+    unit: Option<EstimateUnit>,
+    charging: Option<bool>,
+}
+
+impl BatteryEstimator {
+    // Returns the minutes to empty and to full, only one of them is set at a time
+    pub fn update(&mut self, info: &BatteryInfo, now: Instant) -> (Option<u32>, Option<u32>) {
+        let charging = match info.status.as_deref() {
+            Some("Charging") => true,
+            Some("Discharging") => false,
+            // Full, not charging or unknown, there is nothing to estimate
+            _ => {
+                self.reset();
+                return (None, None);
+            }
+        };
+        let sample = match (info.charge_now, info.current, info.energy_now, info.power) {
+            (Some(now), Some(rate), _, _) => {
+                Some((EstimateUnit::Charge, now, info.charge_full, rate.abs()))
+            }
+            (_, _, Some(now), Some(rate)) => Some((EstimateUnit::Energy, now, None, rate.abs())),
+            _ => None,
+        };
+        let Some((unit, amount, full, rate)) = sample else {
+            self.reset();
+            return (None, None);
+        };
+        if self.unit != Some(unit) || self.charging != Some(charging) {
+            self.reset();
+            self.unit = Some(unit);
+            self.charging = Some(charging);
+        }
+
+        self.add_sample(rate, now);
+        let Some(rate) = self.rate.filter(|rate| *rate > 0.0) else {
+            return (None, None);
+        };
+        if self.samples < MIN_SAMPLES {
+            return (None, None);
+        }
+        let minutes = |amount: f64| Some((amount.max(0.0) / rate * 60.0).round() as u32);
+        if charging {
+            (None, full.and_then(|full| minutes(full - amount)))
+        } else {
+            (minutes(amount), None)
+        }
+    }
+
+    fn add_sample(&mut self, rate: f64, now: Instant) {
+        let elapsed = self
+            .last_sample
+            .map(|last| now.saturating_duration_since(last))
+            .unwrap_or_default();
+        self.last_sample = Some(now);
+        self.samples += 1;
+        self.rate = Some(match self.rate {
+            // Nothing to tell a spike from, like after a first sample of zero when unplugged
+            None => rate,
+            Some(average) if average <= 0.0 => rate,
+            Some(average) => {
+                let rate = rate.clamp(average / SPIKE_RATIO, average * SPIKE_RATIO);
+                // Weighted by time, samples come from uevents and the occasional read between them
+                let alpha =
+                    1.0 - (-elapsed.as_secs_f64() / ESTIMATE_TIME_CONSTANT.as_secs_f64()).exp();
+                average + alpha * (rate - average)
+            }
+        });
+    }
+
+    fn reset(&mut self) {
+        *self = BatteryEstimator::default();
+    }
+}
+
+struct AttributeReader<'a> {
+    dir: &'a Path,
+    errors: &'a mut Vec<String>,
+}
+
+impl AttributeReader<'_> {
+    // Attributes the driver doesn't have are only an error when required
+    async fn read(&mut self, name: &str, required: bool) -> Option<String> {
+        match read_to_string(self.dir.join(name)).await {
+            Ok(value) => Some(value.trim().to_string()),
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => None,
+            Err(e) => {
+                self.errors.push(format!("{}: {}", name, e));
+                None
+            }
+        }
+    }
 
-        let mut previous_state = get_battery_info(&path).await;
-        self.send_unix(unix, previous_state.clone()).await;
+    async fn parse<T: std::str::FromStr>(&mut self, name: &str, required: bool) -> Option<T> {
+        let value = self.read(name, required).await?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.errors
+                    .push(format!("{}: can't parse {:?}", name, value));
+                None
+            }
+        }
+    }
 
-        let mut cmd = Command::new("udevadm")
-            .arg("monitor")
-            .arg("--subsystem-match=power_supply")
-            .arg("--property")
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn udevadm monitor command");
+    // Values sysfs has in micro units
+    async fn micro(&mut self, name: &str) -> Option<f64> {
+        self.parse::<f64>(name, false)
+            .await
+            .map(|value| value / 1_000_000.0)
+    }
+}
 
-        let stdout = cmd.stdout.take().expect("Failed to take stdout");
-        let mut reader = BufReader::new(stdout).lines();
+// None when there is no AC or USB supply at all
+async fn charger_online(power_supply_dir: &Path, errors: &mut Vec<String>) -> Option<bool> {
+    let mut entries = match tokio::fs::read_dir(power_supply_dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(format!("{:?}: {}", power_supply_dir, e));
+            return None;
+        }
+    };
+    let mut online = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let supply_type = read_to_string(entry.path().join("type")).await;
+        if !matches!(supply_type.as_deref().map(str::trim), Ok("Mains" | "USB")) {
+            continue;
+        }
+        match read_to_string(entry.path().join("online")).await {
+            Ok(value) => {
+                online = Some(online.unwrap_or(false) || value.trim() == "1");
+            }
+            Err(e) => errors.push(format!("{:?}: {}", entry.file_name(), e)),
+        }
+    }
+    online
+}
+
+// Reads the battery on every power_supply uevent, and once in a while as long as there is
+// something to estimate since the driver doesn't send one for every change of the current.
+// Listeners each keep their own receiver, so a slow or dead one doesn't hold up the others
+pub struct PowerSupplyMonitor {
+    pub power_supply_dir: PathBuf,
+    pub uevents: UeventSubscription,
+    pub battery_tx: watch::Sender<BatteryInfo>,
+}
 
+impl PowerSupplyMonitor {
+    pub async fn start(&mut self) {
+        info!("Starting PowerSupplyMonitor");
+        let mut estimator = BatteryEstimator::default();
         loop {
+            let mut info = BatteryInfo::read(&self.power_supply_dir).await;
+            let estimating = matches!(info.status.as_deref(), Some("Charging" | "Discharging"));
+            (info.time_to_empty, info.time_to_full) = estimator.update(&info, Instant::now());
+            if !info.errors.is_empty() {
+                warn!("Failed to read the battery: {:?}", info.errors);
+            }
+            self.battery_tx.send_if_modified(|current| {
+                let changed = *current != info;
+                *current = info;
+                changed
+            });
+
             tokio::select! {
-                _ = reader.next_line() => {},
-                _ = sleep(Duration::from_secs(10)) => {}
+                _ = sleep(ESTIMATE_INTERVAL), if estimating => {}
+                uevent = self.uevents.next() => {
+                    if uevent.is_none() {
+                        error!("Uevents for the battery stopped");
+                        break;
+                    }
+                    sleep(Duration::from_millis(100)).await;
+                }
             }
-            sleep(Duration::from_millis(100)).await;
-            self.channel_tx.send(()).await.unwrap();
-            // debug!("Battery state change event detected");
-            let current_state = get_battery_info(&path).await;
-            if previous_state != current_state {
-                debug!("Battery state changed, sending");
-                self.send_unix(unix, current_state.clone()).await;
-                previous_state = current_state;
-            } else {
-                // debug!("Battery state is the same");
+        }
+    }
+}
+
+pub struct BatteryListener {
+    pub battery_rx: watch::Receiver<BatteryInfo>,
+}
+
+#[async_trait]
+impl SocketHandler for BatteryListener {
+    const SOCKET_NAME: &'static str = "battery";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting BatteryListener");
+        loop {
+            let json = serde_json::to_string(&*self.battery_rx.borrow_and_update());
+            match json {
+                Ok(json) => self.send_unix(unix, json).await,
+                Err(e) => error!("Failed to serialize battery info: {}", e),
+            }
+            if self.battery_rx.changed().await.is_err() {
+                error!("Battery sender dropped");
+                break;
             }
         }
     }
 }
 
+// The topics from before the battery one, for panels which still listen on them. They send
+// the bare value, and only when it changed
+async fn forward_battery_value<H: SocketHandler + Sync>(
+    handler: &H,
+    unix: &mut tokio::net::UnixStream,
+    battery_rx: &mut watch::Receiver<BatteryInfo>,
+    value: fn(&BatteryInfo) -> Option<String>,
+) {
+    let mut previous = None;
+    loop {
+        let current = value(&battery_rx.borrow_and_update());
+        if let Some(current) = current.filter(|current| previous.as_ref() != Some(current)) {
+            handler.send_unix(unix, current.clone()).await;
+            previous = Some(current);
+        }
+        if battery_rx.changed().await.is_err() {
+            error!("Battery sender dropped");
+            break;
+        }
+    }
+}
+
+pub struct BatteryStateListener {
+    pub battery_rx: watch::Receiver<BatteryInfo>,
+}
+
+#[async_trait]
+impl SocketHandler for BatteryStateListener {
+    const SOCKET_NAME: &'static str = "battery_state";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting BatteryStateListener");
+        let mut battery_rx = self.battery_rx.clone();
+        forward_battery_value(self, unix, &mut battery_rx, |info| info.status.clone()).await;
+    }
+}
+
 pub struct BatteryPercentListener {
-    pub channel_rx: tokio::sync::mpsc::Receiver<()>,
+    pub battery_rx: watch::Receiver<BatteryInfo>,
 }
 
 #[async_trait]
@@ -77,32 +348,245 @@ impl SocketHandler for BatteryPercentListener {
 
     async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
         info!("Starting BatteryPercentListener");
-        let mut path = PathBuf::from("/sys/class/power_supply/");
-        path.push(BATTERY_DEVICE);
-        path.push("capacity");
+        let mut battery_rx = self.battery_rx.clone();
+        forward_battery_value(self, unix, &mut battery_rx, |info| {
+            info.capacity.map(|capacity| capacity.to_string())
+        })
+        .await;
+    }
+}
 
-        let mut previous_percent = get_battery_info(&path).await;
-        self.send_unix(unix, previous_percent.clone()).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uevent::inject_uevent;
 
-        loop {
-            self.channel_rx.recv().await;
-            sleep(Duration::from_millis(100)).await;
-
-            // debug!("Battery percent change event detected");
-            let current_percent = get_battery_info(&path).await;
-            /*
-            debug!(
-                "Battery percent current: {}, previous: {}",
-                current_percent, previous_percent
-            );
-            */
-            if previous_percent != current_percent {
-                debug!("Battery percent changed, sending");
-                self.send_unix(unix, current_percent.clone()).await;
-                previous_percent = current_percent;
+    const SAMPLE: Duration = Duration::from_secs(30);
+
+    fn sample(status: &str, charge_now: f64, current: f64) -> BatteryInfo {
+        BatteryInfo {
+            status: Some(status.to_string()),
+            charge_now: Some(charge_now),
+            charge_full: Some(3.0),
+            current: Some(current),
+            ..Default::default()
+        }
+    }
+
+    // Samples every 30 seconds from start, the charge follows the current
+    fn run(
+        estimator: &mut BatteryEstimator,
+        start: Instant,
+        status: &str,
+        mut charge: f64,
+        currents: &[f64],
+    ) -> Vec<(Option<u32>, Option<u32>)> {
+        currents
+            .iter()
+            .enumerate()
+            .map(|(i, &current)| {
+                let estimate =
+                    estimator.update(&sample(status, charge, current), start + SAMPLE * i as u32);
+                charge += current * SAMPLE.as_secs_f64() / 3600.0;
+                estimate
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steady_drain() {
+        let mut estimator = BatteryEstimator::default();
+        let estimates = run(
+            &mut estimator,
+            Instant::now(),
+            "Discharging",
+            2.0,
+            &[-0.5; 10],
+        );
+        // Not enough samples yet
+        assert_eq!(estimates[..2], [(None, None), (None, None)]);
+        // 2 Ah at 0.5 A, minus what was drawn by then
+        assert_eq!(estimates[2], (Some(239), None));
+        assert_eq!(estimates[9], (Some(236), None));
+    }
+
+    #[test]
+    fn refresh_spikes_barely_move_the_estimate() {
+        let mut estimator = BatteryEstimator::default();
+        let start = Instant::now();
+        for i in 0..20 {
+            // A full refresh draws six times the idle current for a sample
+            let current = if [5, 6, 12, 17].contains(&i) {
+                -3.0
             } else {
-                // debug!("Battery percent is the same");
-            }
+                -0.5
+            };
+            estimator.update(&sample("Discharging", 2.0, current), start + SAMPLE * i);
+            let rate = estimator.rate.unwrap();
+            assert!((0.5..0.65).contains(&rate), "{}", rate);
         }
     }
+
+    #[test]
+    fn charge_and_discharge_switch() {
+        let mut estimator = BatteryEstimator::default();
+        let start = Instant::now();
+        run(&mut estimator, start, "Discharging", 2.0, &[-0.5; 5]);
+        // Plugged in, the draw so far says nothing about charging
+        let estimates = run(
+            &mut estimator,
+            start + SAMPLE * 5,
+            "Charging",
+            2.0,
+            &[1.0; 4],
+        );
+        assert_eq!(estimates[..2], [(None, None), (None, None)]);
+        assert_eq!(estimates[2], (None, Some(59)));
+        // Full has nothing to estimate, unplugged starts over
+        assert_eq!(
+            estimator.update(&sample("Full", 3.0, 0.0), start + SAMPLE * 9),
+            (None, None)
+        );
+        let estimates = run(
+            &mut estimator,
+            start + SAMPLE * 10,
+            "Discharging",
+            3.0,
+            &[-0.5; 3],
+        );
+        assert_eq!(estimates[2], (Some(359), None));
+    }
+
+    #[test]
+    fn zero_first_sample() {
+        let mut estimator = BatteryEstimator::default();
+        // The driver often reads no current right after unplugging
+        let estimates = run(
+            &mut estimator,
+            Instant::now(),
+            "Discharging",
+            2.0,
+            &[0.0, -0.5, -0.5, -0.5],
+        );
+        assert_eq!(estimates[..2], [(None, None), (None, None)]);
+        assert!(
+            estimates[2]
+                .0
+                .is_some_and(|minutes| (230..=240).contains(&minutes))
+        );
+        assert!(estimates[3].0.is_some());
+    }
+
+    #[test]
+    fn energy_when_there_is_no_charge() {
+        let mut estimator = BatteryEstimator::default();
+        let info = BatteryInfo {
+            status: Some("Discharging".to_string()),
+            energy_now: Some(8.0),
+            power: Some(-2.0),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        for i in 0..2 {
+            estimator.update(&info, start + SAMPLE * i);
+        }
+        assert_eq!(
+            estimator.update(&info, start + SAMPLE * 2),
+            (Some(240), None)
+        );
+    }
+
+    // A power_supply directory with the battery and a charger, removed when dropped
+    struct TempPowerSupply(PathBuf);
+
+    impl TempPowerSupply {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "quill-power-supply-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join(BATTERY_DEVICE)).unwrap();
+            std::fs::create_dir_all(dir.join("usb")).unwrap();
+            std::fs::write(dir.join("usb/type"), "USB\n").unwrap();
+            let supply = TempPowerSupply(dir);
+            supply.set("usb/online", "0");
+            supply.set_battery("57", "Discharging");
+            supply
+        }
+
+        fn set(&self, name: &str, value: &str) {
+            std::fs::write(self.0.join(name), format!("{}\n", value)).unwrap();
+        }
+
+        fn set_battery(&self, capacity: &str, status: &str) {
+            self.set(&format!("{}/capacity", BATTERY_DEVICE), capacity);
+            self.set(&format!("{}/status", BATTERY_DEVICE), status);
+        }
+    }
+
+    impl Drop for TempPowerSupply {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const BATTERY_UEVENT: &[u8] =
+        b"change@/devices/platform/rk817-battery/power_supply/rk817-battery\0SUBSYSTEM=power_supply\0";
+    const USB_UEVENT: &[u8] =
+        b"change@/devices/platform/usb/power_supply/usb\0SUBSYSTEM=power_supply\0POWER_SUPPLY_ONLINE=1\0";
+    const BACKLIGHT_UEVENT: &[u8] =
+        b"change@/devices/platform/backlight_cool/backlight/backlight_cool\0SUBSYSTEM=backlight\0";
+
+    async fn changed(battery_rx: &mut watch::Receiver<BatteryInfo>) -> BatteryInfo {
+        tokio::time::timeout(Duration::from_secs(2), battery_rx.changed())
+            .await
+            .expect("no battery update")
+            .unwrap();
+        battery_rx.borrow_and_update().clone()
+    }
+
+    #[tokio::test]
+    async fn monitor_reads_again_on_power_supply_uevents() {
+        let supply = TempPowerSupply::new("monitor");
+        let (uevent_tx, _) = tokio::sync::broadcast::channel(8);
+        let (battery_tx, mut battery_rx) = watch::channel(BatteryInfo::default());
+        let mut monitor = PowerSupplyMonitor {
+            power_supply_dir: supply.0.clone(),
+            uevents: UeventSubscription::new(&uevent_tx, "power_supply"),
+            battery_tx,
+        };
+        let monitor = tokio::spawn(async move { monitor.start().await });
+
+        let info = changed(&mut battery_rx).await;
+        assert_eq!(info.capacity, Some(57));
+        assert_eq!(info.status.as_deref(), Some("Discharging"));
+        assert_eq!(info.charger_online, Some(false));
+        assert!(info.errors.is_empty(), "{:?}", info.errors);
+
+        // Another subsystem doesn't make it read
+        supply.set_battery("56", "Discharging");
+        assert!(inject_uevent(&uevent_tx, BACKLIGHT_UEVENT));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!battery_rx.has_changed().unwrap());
+
+        assert!(inject_uevent(&uevent_tx, BATTERY_UEVENT));
+        assert_eq!(changed(&mut battery_rx).await.capacity, Some(56));
+
+        // Plugged in, the charger sends the uevent
+        supply.set("usb/online", "1");
+        supply.set_battery("56", "Charging");
+        assert!(inject_uevent(&uevent_tx, USB_UEVENT));
+        let info = changed(&mut battery_rx).await;
+        assert_eq!(info.status.as_deref(), Some("Charging"));
+        assert_eq!(info.charger_online, Some(true));
+
+        // The monitor stops with the uevent socket
+        drop(uevent_tx);
+        tokio::time::timeout(Duration::from_secs(2), monitor)
+            .await
+            .expect("monitor still running")
+            .unwrap();
+    }
 }
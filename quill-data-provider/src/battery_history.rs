@@ -0,0 +1,481 @@
+use std::{
+    fs::OpenOptions,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use enums::Requests;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tokio::sync::{broadcast, watch};
+
+use crate::battery::BatteryInfo;
+use crate::config::state_path;
+use crate::listener::SocketHandler;
+
+pub const HISTORY_NAME: &str = "battery_history.bin";
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(300);
+// 30 days of samples
+const HISTORY_SLOTS: u32 = 30 * 24 * 12;
+// The provider doesn't run while suspended, a gap this long between samples was a suspend
+// (or a time the provider wasn't running, which can't be told apart)
+const SUSPEND_GAP: u32 = 2 * SAMPLE_INTERVAL.as_secs() as u32;
+// Points sent to the panel, longer ranges are thinned out
+const MAX_PUBLISHED_SAMPLES: usize = 300;
+
+const MAGIC: &[u8; 4] = b"QBH1";
+// Magic, slots, next slot, count
+const HEADER_SIZE: u64 = 16;
+// Time, capacity, flags
+const RECORD_SIZE: u64 = 6;
+const FLAG_CHARGING: u8 = 1;
+const FLAG_CHARGER_ONLINE: u8 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct HistorySample {
+    // Unix seconds
+    pub time: u32,
+    pub capacity: u8,
+    pub charging: bool,
+    pub charger_online: bool,
+}
+
+impl HistorySample {
+    pub fn from_info(info: &BatteryInfo, time: SystemTime) -> Option<Self> {
+        Some(HistorySample {
+            time: time.duration_since(UNIX_EPOCH).ok()?.as_secs() as u32,
+            capacity: info.capacity?,
+            charging: info.status.as_deref() == Some("Charging"),
+            charger_online: info.charger_online.unwrap_or(false),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; RECORD_SIZE as usize] {
+        let mut flags = 0;
+        if self.charging {
+            flags |= FLAG_CHARGING;
+        }
+        if self.charger_online {
+            flags |= FLAG_CHARGER_ONLINE;
+        }
+        let time = self.time.to_le_bytes();
+        [time[0], time[1], time[2], time[3], self.capacity, flags]
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        HistorySample {
+            time: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            capacity: bytes[4],
+            charging: bytes[5] & FLAG_CHARGING != 0,
+            charger_online: bytes[5] & FLAG_CHARGER_ONLINE != 0,
+        }
+    }
+}
+
+// A fixed size file of samples, the oldest one is overwritten once it's full. A file with
+// another layout is started over
+pub struct HistoryFile {
+    pub path: PathBuf,
+    pub slots: u32,
+}
+
+impl HistoryFile {
+    pub fn at(path: &Path) -> Self {
+        HistoryFile {
+            path: path.to_path_buf(),
+            slots: HISTORY_SLOTS,
+        }
+    }
+
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(state_path(HISTORY_NAME))
+    }
+
+    pub fn append(&self, sample: HistorySample) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        let (next, count) = match self.read_header(&mut file)? {
+            Some(header) => header,
+            None => {
+                file.set_len(0)?;
+                (0, 0)
+            }
+        };
+
+        file.seek(SeekFrom::Start(HEADER_SIZE + next as u64 * RECORD_SIZE))?;
+        file.write_all(&sample.to_bytes())?;
+        let next = (next + 1) % self.slots;
+        let count = (count + 1).min(self.slots);
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(MAGIC)?;
+        for value in [self.slots, next, count] {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Oldest first
+    pub fn read_all(&self) -> std::io::Result<Vec<HistorySample>> {
+        let mut file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let Some((next, count)) = self.read_header(&mut file)? else {
+            return Ok(Vec::new());
+        };
+        let mut records = Vec::new();
+        file.seek(SeekFrom::Start(HEADER_SIZE))?;
+        file.read_to_end(&mut records)?;
+
+        let first = if count < self.slots { 0 } else { next };
+        Ok((0..count)
+            .filter_map(|i| {
+                let start = ((first + i) % self.slots) as usize * RECORD_SIZE as usize;
+                records
+                    .get(start..start + RECORD_SIZE as usize)
+                    .map(HistorySample::from_bytes)
+            })
+            .collect())
+    }
+
+    // None for an empty file or one with another layout
+    fn read_header(&self, file: &mut std::fs::File) -> std::io::Result<Option<(u32, u32)>> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let (slots, next, count) = (u32_at(4), u32_at(8), u32_at(12));
+        if &header[0..4] != MAGIC || slots != self.slots || next >= slots || count > slots {
+            warn!("Starting {:?} over, it has another layout", self.path);
+            return Ok(None);
+        }
+        Ok(Some((next, count)))
+    }
+}
+
+// Drain is in percent per hour. Awake and suspended are told apart by the gaps in the samples
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct HistoryStats {
+    pub awake_hours: f64,
+    pub awake_drain: Option<f64>,
+    pub suspended_hours: f64,
+    pub suspended_drain: Option<f64>,
+    // Percent gained while charging, and that as full charge cycles
+    pub charged: u32,
+    pub cycles: f64,
+}
+
+impl HistoryStats {
+    pub fn from_samples(samples: &[HistorySample]) -> Self {
+        let mut stats = HistoryStats::default();
+        let (mut awake_drained, mut suspended_drained) = (0.0, 0.0);
+        for pair in samples.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let gap = b.time.saturating_sub(a.time);
+            let hours = gap as f64 / 3600.0;
+            let change = b.capacity as f64 - a.capacity as f64;
+            if a.charging || b.charging || a.charger_online || b.charger_online {
+                stats.charged += change.max(0.0) as u32;
+            } else if gap >= SUSPEND_GAP {
+                stats.suspended_hours += hours;
+                suspended_drained -= change;
+            } else {
+                stats.awake_hours += hours;
+                awake_drained -= change;
+            }
+        }
+        let per_hour = |drained: f64, hours: f64| (hours > 0.0).then(|| drained / hours);
+        stats.awake_drain = per_hour(awake_drained, stats.awake_hours);
+        stats.suspended_drain = per_hour(suspended_drained, stats.suspended_hours);
+        stats.cycles = stats.charged as f64 / 100.0;
+        stats
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryInfo {
+    hours: u32,
+    samples: Vec<HistorySample>,
+    stats: HistoryStats,
+}
+
+// Records a sample every few minutes and answers BatteryHistory requests on the
+// battery_history topic
+pub struct BatteryHistoryListener {
+    pub channel_rx: broadcast::Receiver<Requests>,
+    pub history_tx: watch::Sender<String>,
+    pub file: HistoryFile,
+    pub battery_rx: watch::Receiver<BatteryInfo>,
+}
+
+impl BatteryHistoryListener {
+    pub async fn start(&mut self) {
+        info!("Starting BatteryHistoryListener");
+        // Not right away, the battery may not be read yet
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + SAMPLE_INTERVAL,
+            SAMPLE_INTERVAL,
+        );
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.record(),
+                res = self.channel_rx.recv() => {
+                    if let Ok(Requests::BatteryHistory(hours)) = res {
+                        self.publish(hours);
+                    }
+                }
+            }
+        }
+    }
+
+    fn record(&self) {
+        let info = self.battery_rx.borrow().clone();
+        let Some(sample) = HistorySample::from_info(&info, SystemTime::now()) else {
+            warn!("No battery sample to record: {:?}", info.errors);
+            return;
+        };
+        debug!("Recording battery sample {:?}", sample);
+        if let Err(e) = self.file.append(sample) {
+            error!("Failed to write to {:?}: {}", self.file.path, e);
+        }
+    }
+
+    fn publish(&self, hours: u32) {
+        let samples = match self.file.read_all() {
+            Ok(samples) => samples,
+            Err(e) => {
+                error!("Failed to read {:?}: {}", self.file.path, e);
+                return;
+            }
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        let since = now.saturating_sub(hours.saturating_mul(3600));
+        let samples: Vec<HistorySample> = samples.into_iter().filter(|s| s.time >= since).collect();
+        let stats = HistoryStats::from_samples(&samples);
+        let info = HistoryInfo {
+            hours,
+            samples: thin_out(samples, MAX_PUBLISHED_SAMPLES),
+            stats,
+        };
+        match serde_json::to_string(&info) {
+            Ok(json) => {
+                self.history_tx.send_replace(json);
+            }
+            Err(e) => error!("Failed to serialize battery history: {}", e),
+        }
+    }
+}
+
+// Every nth sample, keeping the last one
+fn thin_out(samples: Vec<HistorySample>, max: usize) -> Vec<HistorySample> {
+    if samples.len() <= max || max < 2 {
+        return samples;
+    }
+    let step = samples.len().div_ceil(max - 1);
+    let last = samples.last().copied();
+    let mut thinned: Vec<HistorySample> = samples.into_iter().step_by(step).collect();
+    if thinned.last().copied() != last
+        && let Some(last) = last
+    {
+        thinned.push(last);
+    }
+    thinned
+}
+
+pub struct BatteryHistoryTopicListener {
+    pub history_rx: watch::Receiver<String>,
+}
+
+#[async_trait]
+impl SocketHandler for BatteryHistoryTopicListener {
+    const SOCKET_NAME: &'static str = "battery_history";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting BatteryHistoryTopicListener");
+        let mut history_rx = self.history_rx.clone();
+        self.forward_watch(unix, &mut history_rx).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A history of a few slots in its own file, removed when dropped
+    struct TempHistory(HistoryFile);
+
+    impl TempHistory {
+        fn new(name: &str, slots: u32) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "quill-history-{}-{}.bin",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            TempHistory(HistoryFile { path, slots })
+        }
+    }
+
+    impl Drop for TempHistory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0.path);
+        }
+    }
+
+    fn sample(time: u32, capacity: u8) -> HistorySample {
+        HistorySample {
+            time,
+            capacity,
+            charging: false,
+            charger_online: false,
+        }
+    }
+
+    fn charging(time: u32, capacity: u8) -> HistorySample {
+        HistorySample {
+            charging: true,
+            charger_online: true,
+            ..sample(time, capacity)
+        }
+    }
+
+    fn times(samples: &[HistorySample]) -> Vec<u32> {
+        samples.iter().map(|sample| sample.time).collect()
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let history = TempHistory::new("missing", 4);
+        assert_eq!(history.0.read_all().unwrap(), []);
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let history = TempHistory::new("round-trip", 4);
+        let samples = [sample(100, 80), charging(400, 81), sample(700, 79)];
+        for sample in samples {
+            history.0.append(sample).unwrap();
+        }
+        assert_eq!(history.0.read_all().unwrap(), samples);
+    }
+
+    #[test]
+    fn wraps_around_oldest_first() {
+        let history = TempHistory::new("wrap", 4);
+        for time in 1..=4 {
+            history.0.append(sample(time, 50)).unwrap();
+        }
+        assert_eq!(times(&history.0.read_all().unwrap()), [1, 2, 3, 4]);
+        for time in 5..=10 {
+            history.0.append(sample(time, 50)).unwrap();
+        }
+        assert_eq!(times(&history.0.read_all().unwrap()), [7, 8, 9, 10]);
+        let size = std::fs::metadata(&history.0.path).unwrap().len();
+        assert_eq!(size, HEADER_SIZE + 4 * RECORD_SIZE);
+    }
+
+    #[test]
+    fn another_layout_starts_over() {
+        let history = TempHistory::new("layout", 4);
+        for time in 1..=3 {
+            history.0.append(sample(time, 50)).unwrap();
+        }
+        // Written with more slots
+        let resized = HistoryFile {
+            path: history.0.path.clone(),
+            slots: 8,
+        };
+        assert_eq!(resized.read_all().unwrap(), []);
+        resized.append(sample(4, 50)).unwrap();
+        assert_eq!(times(&resized.read_all().unwrap()), [4]);
+        assert_eq!(history.0.read_all().unwrap(), []);
+    }
+
+    #[test]
+    fn corrupt_header_starts_over() {
+        let history = TempHistory::new("corrupt", 4);
+        for time in 1..=3 {
+            history.0.append(sample(time, 50)).unwrap();
+        }
+        let mut bytes = std::fs::read(&history.0.path).unwrap();
+        // Next slot past the end
+        bytes[8..12].copy_from_slice(&9u32.to_le_bytes());
+        std::fs::write(&history.0.path, &bytes).unwrap();
+        assert_eq!(history.0.read_all().unwrap(), []);
+
+        bytes[0..4].copy_from_slice(b"JUNK");
+        std::fs::write(&history.0.path, &bytes).unwrap();
+        history.0.append(sample(10, 40)).unwrap();
+        assert_eq!(history.0.read_all().unwrap(), [sample(10, 40)]);
+
+        // Cut off in the header
+        std::fs::write(&history.0.path, &bytes[..6]).unwrap();
+        assert_eq!(history.0.read_all().unwrap(), []);
+    }
+
+    #[test]
+    fn suspend_gaps_are_told_apart() {
+        let interval = SAMPLE_INTERVAL.as_secs() as u32;
+        // An hour awake, 12 percent
+        let mut samples: Vec<HistorySample> = (0..=12)
+            .map(|i| sample(i * interval, 90 - i as u8))
+            .collect();
+        samples.extend([
+            // Suspended for ten hours, 5 percent
+            sample(12 * interval + 36000, 73),
+            // Charging doesn't count as drain
+            charging(13 * interval + 36000, 80),
+            charging(14 * interval + 36000, 95),
+            sample(15 * interval + 36000, 95),
+        ]);
+        let stats = HistoryStats::from_samples(&samples);
+        assert_eq!(stats.awake_hours, 1.0);
+        assert_eq!(stats.awake_drain, Some(12.0));
+        assert_eq!(stats.suspended_hours, 10.0);
+        assert_eq!(stats.suspended_drain, Some(0.5));
+        assert_eq!(stats.charged, 22);
+        assert_eq!(stats.cycles, 0.22);
+    }
+
+    #[test]
+    fn no_drain_without_time() {
+        assert_eq!(HistoryStats::from_samples(&[]), HistoryStats::default());
+        let stats = HistoryStats::from_samples(&[charging(0, 50), charging(300, 60)]);
+        assert_eq!(stats.awake_drain, None);
+        assert_eq!(stats.suspended_drain, None);
+        assert_eq!(stats.charged, 10);
+    }
+
+    #[test]
+    fn thin_out_keeps_the_last_sample() {
+        let samples: Vec<HistorySample> = (0..10).map(|time| sample(time, 50)).collect();
+        assert_eq!(thin_out(samples.clone(), 10), samples);
+        assert_eq!(thin_out(samples.clone(), 1), samples);
+        assert_eq!(times(&thin_out(samples.clone(), 4)), [0, 4, 8, 9]);
+        assert_eq!(times(&thin_out(samples.clone(), 5)), [0, 3, 6, 9]);
+        assert_eq!(times(&thin_out(samples, 3)), [0, 5, 9]);
+
+        let samples: Vec<HistorySample> = (0..1000).map(|time| sample(time, 50)).collect();
+        let thinned = thin_out(samples, MAX_PUBLISHED_SAMPLES);
+        assert!(thinned.len() <= MAX_PUBLISHED_SAMPLES);
+        assert_eq!(thinned.last().unwrap().time, 999);
+    }
+}
@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+use enums::Requests;
+use log::{debug, error, info, warn};
+use quill_data_provider_lib::SliderMapping;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::auto_brightness::AutoBrightnessConfig;
+use crate::backlight::BacklightConfig;
+use crate::frontlight_schedule::ScheduleConfig;
+use crate::gamma::GammaConfig;
+use crate::gestures::GestureConfig;
+use crate::listener::SocketHandler;
+use crate::low_battery::LowBatteryConfig;
+use crate::orientation::OrientationConfig;
+use crate::pen::PenConfig;
+
+pub const CONFIG_HOME_DIR: &str = "/.config/quill-data-provider/";
+pub const CONFIG_NAME: &str = "config.ron";
+// For what the provider keeps by itself, like the battery history
+pub const STATE_HOME_DIR: &str = "/.local/state/quill-data-provider/";
+
+pub fn config_path() -> String {
+    let username = std::env::var("USER").unwrap_or_default();
+    format!("/home/{}{}{}", username, CONFIG_HOME_DIR, CONFIG_NAME)
+}
+
+pub fn state_path(name: &str) -> String {
+    let username = std::env::var("USER").unwrap_or_default();
+    format!("/home/{}{}{}", username, STATE_HOME_DIR, name)
+}
+
+// Every section has defaults, so a config only needs what it changes
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderConfig {
+    pub sliders: SliderConfig,
+    pub gamma: GammaConfig,
+    pub gestures: GestureConfig,
+    pub orientation: OrientationConfig,
+    pub pen: PenConfig,
+    pub low_battery: LowBatteryConfig,
+    pub backlight: BacklightConfig,
+    pub schedule: ScheduleConfig,
+    pub auto_brightness: AutoBrightnessConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SliderConfig {
+    pub threshold: SliderMapping,
+    pub redraw_delay: SliderMapping,
+    // Percent of the frontlight and of each backlight channel
+    pub brightness: SliderMapping,
+    // From cool to warm
+    pub temperature: SliderMapping,
+    pub volume: SliderMapping,
+}
+
+impl Default for SliderConfig {
+    fn default() -> Self {
+        Self {
+            // The 1-100 range the eww panel has always sent, the sliders topic is optional
+            threshold: SliderMapping::new(2, 15, "").with_slider(1, 100),
+            redraw_delay: SliderMapping::new(10, 300, "ms").with_slider(1, 100),
+            brightness: SliderMapping::new(0, 100, "%"),
+            temperature: SliderMapping::new(0, 100, ""),
+            volume: SliderMapping::new(0, 100, "%"),
+        }
+    }
+}
+
+impl ProviderConfig {
+    // A missing file gets the defaults written, a broken one is left alone for the user to fix
+    pub fn load(path: &str) -> Self {
+        match ProviderConfig::read(path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Can't read {}, using the default config: {}", path, e);
+                let config = ProviderConfig::default();
+                config.write_default(path);
+                config
+            }
+            Err(e) => {
+                error!("{}, using the default config", e);
+                ProviderConfig::default()
+            }
+        }
+    }
+
+    pub fn read(path: &str) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        ron::from_str(&contents).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to parse {}: {}", path, e),
+            )
+        })
+    }
+
+    fn write_default(&self, path: &str) {
+        if let Some(parent) = std::path::Path::new(path).parent()
+            && let Err(e) = std::fs::create_dir_all(parent)
+        {
+            error!("Failed to create {:?}: {}", parent, e);
+            return;
+        }
+        match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(contents) => {
+                if let Err(e) = std::fs::write(path, contents) {
+                    error!("Failed to write the default config to {}: {}", path, e);
+                }
+            }
+            Err(e) => error!("Failed to serialize the default config: {}", e),
+        }
+    }
+}
+
+// Reloads the config on request. Gestures, the sliders and the pen's drawing preset follow
+// it, other sections are read once at startup
+pub struct ConfigListener {
+    pub channel_rx: tokio::sync::broadcast::Receiver<Requests>,
+    pub config_tx: tokio::sync::watch::Sender<ProviderConfig>,
+    pub sliders_tx: tokio::sync::watch::Sender<String>,
+}
+
+impl ConfigListener {
+    pub async fn start(&mut self) {
+        info!("Starting ConfigListener");
+        loop {
+            match self.channel_rx.recv().await {
+                Ok(Requests::ReloadConfig) => self.reload(),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => {
+                    error!("Request channel closed");
+                    break;
+                }
+            }
+        }
+    }
+
+    // A file with a mistake keeps the config we have, a deleted one goes back to the defaults
+    fn reload(&mut self) {
+        let path = config_path();
+        let config = match ProviderConfig::read(&path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ProviderConfig::default(),
+            Err(e) => {
+                error!("Keeping the current config: {}", e);
+                return;
+            }
+        };
+        debug!("Reloaded config: {:#?}", config);
+        let sliders_json = serde_json::to_string(&config.sliders).unwrap_or_default();
+        self.sliders_tx.send_if_modified(|current| {
+            let changed = *current != sliders_json;
+            *current = sliders_json;
+            changed
+        });
+        self.config_tx.send_replace(config);
+    }
+}
+
+// The slider mappings, so the panel can set its ranges and show real units
+pub struct SlidersListener {
+    pub sliders_rx: tokio::sync::watch::Receiver<String>,
+}
+
+#[async_trait]
+impl SocketHandler for SlidersListener {
+    const SOCKET_NAME: &'static str = "sliders";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting SlidersListener");
+        let mut sliders_rx = self.sliders_rx.clone();
+        self.forward_watch(unix, &mut sliders_rx).await;
+    }
+}
@@ -1,9 +1,14 @@
-use log::{debug, error};
+use log::{debug, error, warn};
+use quill_data_provider_lib::driver::{
+    DriverState, PureDriverMode, RenderHint, read_dither_mode, read_redraw_delay,
+};
 use quill_data_provider_lib::{
     BitDepth, Conversion, Dithering, DriverMode, PINENOTE_ENABLE_SOCKET, Redraw, RedrawOptions,
     ThresholdLevel, run_cmd,
 };
-use std::str::FromStr;
+use std::{fmt, io};
+
+use crate::config::SliderConfig;
 use tokio::{io::AsyncWriteExt, net::UnixStream};
 
 // enum ScreenOptions {
@@ -16,7 +21,7 @@ pub async fn refresh_screen() {
     run_cmd("busctl --user call org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 GlobalRefresh").await;
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct EwwScreenConfig {
     pub window_settings: bool,
     driver_normal: bool,
@@ -28,10 +33,10 @@ pub struct EwwScreenConfig {
     bitdepth_y2: bool,
     bitdepth_y4: bool,
     conv_thresholding: bool,
-    thresholding_level_value: u8,
+    thresholding_level_value: i32,
     conv_dithering: bool,
     redraw_fastdrawing: bool,
-    redraw_level_value: u16,
+    redraw_level_value: i32,
     redraw_disablefastdrawing: bool,
 }
 
@@ -66,7 +71,7 @@ fn parse_number<T: std::str::FromStr + std::fmt::Debug>(state: &str, key: &str,
 }
 
 impl EwwScreenConfig {
-    pub async fn get_eww_screen_config(state: &str) -> Self {
+    pub async fn get_eww_screen_config(state: &str, sliders: &SliderConfig) -> Self {
         Self {
             window_settings: parse_bool(state, "per_window_settings"),
             driver_normal: parse_bool(state, "driver_normal_mode"),
@@ -78,14 +83,124 @@ impl EwwScreenConfig {
             bitdepth_y2: parse_bool(state, "bitdepth_y2"),
             bitdepth_y4: parse_bool(state, "bitdepth_y4"),
             conv_thresholding: parse_bool(state, "conversion_thresholding"),
-            thresholding_level_value: parse_number(state, "thresholding_level_value", 39),
+            thresholding_level_value: parse_number(
+                state,
+                "thresholding_level_value",
+                sliders
+                    .threshold
+                    .to_slider(ThresholdLevel::default().to_u8() as i32),
+            ),
             conv_dithering: parse_bool(state, "conversion_dithering"),
             redraw_fastdrawing: parse_bool(state, "redraw_fast_drawing"),
-            redraw_level_value: parse_number(state, "redraw_level_value", 25),
+            redraw_level_value: parse_number(
+                state,
+                "redraw_level_value",
+                sliders
+                    .redraw_delay
+                    .to_slider(RedrawOptions::default().delay as i32),
+            ),
             redraw_disablefastdrawing: parse_bool(state, "redraw_disabled"),
         }
     }
 
+    // The panel showing a mode. What the mode doesn't use keeps the value it has in the panel
+    pub fn with_driver_mode(&self, mode: &DriverMode, sliders: &SliderConfig) -> Self {
+        let mut config = self.clone();
+        let set_dithering = |config: &mut Self, dithering: &Dithering| {
+            config.dithering_bayer = *dithering == Dithering::Bayer;
+            config.dithering_blue_noise16 = *dithering == Dithering::BlueNoise16;
+            config.dithering_blue_noise32 = *dithering == Dithering::BlueNoise32;
+        };
+        config.driver_fast = matches!(mode, DriverMode::Fast(_));
+        config.driver_normal = matches!(mode, DriverMode::Normal(_));
+        let bit_depth = match mode {
+            DriverMode::Fast(dithering) => {
+                set_dithering(&mut config, dithering);
+                return config;
+            }
+            DriverMode::Normal(bit_depth) => bit_depth,
+        };
+        config.bitdepth_y1 = matches!(bit_depth, BitDepth::Y1(..));
+        config.bitdepth_y2 = matches!(bit_depth, BitDepth::Y2(..));
+        config.bitdepth_y4 = matches!(bit_depth, BitDepth::Y4(..));
+        let (conversion, redraw) = match bit_depth {
+            BitDepth::Y1(conversion, level) => {
+                if *conversion == Conversion::Thresholding {
+                    config.thresholding_level_value =
+                        sliders.threshold.to_slider(level.to_u8() as i32);
+                }
+                (Some(conversion), None)
+            }
+            BitDepth::Y2(conversion, redraw) => (Some(conversion), Some(redraw)),
+            BitDepth::Y4(redraw) => (None, Some(redraw)),
+        };
+        if let Some(conversion) = conversion {
+            config.conv_thresholding = *conversion == Conversion::Thresholding;
+            config.conv_dithering = !config.conv_thresholding;
+            if let Conversion::Dithering(dithering) = conversion {
+                set_dithering(&mut config, dithering);
+            }
+        }
+        if let Some(redraw) = redraw {
+            config.redraw_fastdrawing = matches!(redraw, Redraw::FastDrawing(_));
+            config.redraw_disablefastdrawing = !config.redraw_fastdrawing;
+            if let Redraw::FastDrawing(options) = redraw {
+                config.redraw_level_value = sliders.redraw_delay.to_slider(options.delay as i32);
+            }
+        }
+        config
+    }
+
+    fn eww_vars(&self) -> [(&'static str, String); 14] {
+        [
+            ("driver_normal_mode", self.driver_normal.to_string()),
+            ("driver_fast_mode", self.driver_fast.to_string()),
+            ("dithering_bayer", self.dithering_bayer.to_string()),
+            (
+                "dithering_bluenoise16",
+                self.dithering_blue_noise16.to_string(),
+            ),
+            (
+                "dithering_bluenoise32",
+                self.dithering_blue_noise32.to_string(),
+            ),
+            ("bitdepth_y1", self.bitdepth_y1.to_string()),
+            ("bitdepth_y2", self.bitdepth_y2.to_string()),
+            ("bitdepth_y4", self.bitdepth_y4.to_string()),
+            (
+                "conversion_thresholding",
+                self.conv_thresholding.to_string(),
+            ),
+            (
+                "thresholding_level_value",
+                self.thresholding_level_value.to_string(),
+            ),
+            ("conversion_dithering", self.conv_dithering.to_string()),
+            ("redraw_fast_drawing", self.redraw_fastdrawing.to_string()),
+            ("redraw_level_value", self.redraw_level_value.to_string()),
+            (
+                "redraw_disabled",
+                self.redraw_disablefastdrawing.to_string(),
+            ),
+        ]
+    }
+
+    // Only what differs from the panel is updated, so this is a no-op for settings from the panel
+    pub async fn update_eww(&self, current: &EwwScreenConfig) {
+        let updates: Vec<String> = self
+            .eww_vars()
+            .into_iter()
+            .zip(current.eww_vars())
+            .filter(|(new, old)| new != old)
+            .map(|((key, value), _)| format!("{}={}", key, value))
+            .collect();
+        if !updates.is_empty() {
+            let cmd = format!("eww --no-daemonize update {}", updates.join(" "));
+            debug!("Running eww update cmd: {}", cmd);
+            run_cmd(&cmd).await;
+        }
+    }
+
     pub async fn set_window_settings(&self) {
         match UnixStream::connect(PINENOTE_ENABLE_SOCKET).await {
             Ok(mut stream) => {
@@ -111,7 +226,15 @@ impl EwwScreenConfig {
     }
 }
 
-pub async fn eww_screen_config_to_enum(config: &EwwScreenConfig) -> DriverMode {
+fn threshold_from_slider(slider: i32, sliders: &SliderConfig) -> ThresholdLevel {
+    let level = sliders.threshold.to_value(slider).clamp(2, 15) as u8;
+    ThresholdLevel::try_from(level).unwrap_or_default()
+}
+
+pub async fn eww_screen_config_to_enum(
+    config: &EwwScreenConfig,
+    sliders: &SliderConfig,
+) -> DriverMode {
     fn get_dithering(config: &EwwScreenConfig) -> Dithering {
         if config.dithering_bayer {
             return Dithering::Bayer;
@@ -126,20 +249,18 @@ pub async fn eww_screen_config_to_enum(config: &EwwScreenConfig) -> DriverMode {
     }
     let dithering = get_dithering(config);
 
-    fn get_redraw(config: &EwwScreenConfig) -> Redraw {
+    let get_redraw = |config: &EwwScreenConfig| -> Redraw {
         if config.redraw_fastdrawing {
-            // map to 10-300
-            let delay_drawing =
-                ((config.redraw_level_value - 1) as f32 / 99.0 * 290.0 + 10.0).round() as u16;
+            let delay = sliders.redraw_delay.to_value(config.redraw_level_value);
             return Redraw::FastDrawing(RedrawOptions {
-                delay: delay_drawing,
+                delay: delay.clamp(0, u16::MAX as i32) as u16,
             });
         }
         if config.redraw_disablefastdrawing {
             return Redraw::DisableFastDrawing;
         }
         panic!("No redraw, what?");
-    }
+    };
     let redraw = get_redraw(config);
 
     let get_conversion = |config: &EwwScreenConfig| -> Conversion {
@@ -157,7 +278,7 @@ pub async fn eww_screen_config_to_enum(config: &EwwScreenConfig) -> DriverMode {
         if config.bitdepth_y1 {
             return BitDepth::Y1(
                 conversion,
-                ThresholdLevel::get_from_eww(config.thresholding_level_value),
+                threshold_from_slider(config.thresholding_level_value, sliders),
             );
         }
         if config.bitdepth_y2 {
@@ -183,146 +304,6 @@ pub async fn eww_screen_config_to_enum(config: &EwwScreenConfig) -> DriverMode {
     get_mode(config)
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum PureBitDepth {
-    Y1,
-    Y2,
-    Y4,
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum PureConversion {
-    Thresholding,
-    Dithering,
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum PureRedraw {
-    FastDrawing,        // R
-    DisableFastDrawing, // r
-}
-
-// Impl string functions for render hint enums
-impl ToString for PureRedraw {
-    fn to_string(&self) -> String {
-        match self {
-            PureRedraw::FastDrawing => "R".into(),
-            PureRedraw::DisableFastDrawing => "r".into(),
-        }
-    }
-}
-
-impl FromStr for PureRedraw {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "R" => Ok(PureRedraw::FastDrawing),
-            "r" => Ok(PureRedraw::DisableFastDrawing),
-            _ => Err(()),
-        }
-    }
-}
-
-impl ToString for PureBitDepth {
-    fn to_string(&self) -> String {
-        match self {
-            PureBitDepth::Y1 => "Y1".into(),
-            PureBitDepth::Y2 => "Y2".into(),
-            PureBitDepth::Y4 => "Y4".into(),
-        }
-    }
-}
-
-impl FromStr for PureBitDepth {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "Y1" => Ok(PureBitDepth::Y1),
-            "Y2" => Ok(PureBitDepth::Y2),
-            "Y4" => Ok(PureBitDepth::Y4),
-            _ => Err(()),
-        }
-    }
-}
-
-impl ToString for PureConversion {
-    fn to_string(&self) -> String {
-        match self {
-            PureConversion::Thresholding => "T".into(),
-            PureConversion::Dithering => "D".into(),
-        }
-    }
-}
-
-impl FromStr for PureConversion {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "T" => Ok(PureConversion::Thresholding),
-            "D" => Ok(PureConversion::Dithering),
-            _ => Err(()),
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct RenderHint {
-    pub bit_depth: PureBitDepth,
-    pub conversion: PureConversion,
-    pub redraw: PureRedraw,
-}
-
-impl ToString for RenderHint {
-    fn to_string(&self) -> String {
-        format!(
-            "{}|{}|{}",
-            self.bit_depth.to_string(),
-            self.conversion.to_string(),
-            self.redraw.to_string()
-        )
-    }
-}
-
-impl FromStr for RenderHint {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        debug!("RenderHint FromStr received: {}", s);
-        let mut parts = s.split('|');
-
-        let bd = parts.next().ok_or(())?;
-        let cv = parts.next().unwrap_or("T");
-        let rw = parts.next().unwrap_or("r");
-
-        let bit_depth = bd.parse()?;
-        let conversion = cv.parse().unwrap_or(PureConversion::Thresholding);
-        let redraw = rw.parse().unwrap_or(PureRedraw::FastDrawing);
-
-        Ok(RenderHint {
-            bit_depth,
-            conversion,
-            redraw,
-        })
-    }
-}
-
-impl RenderHint {
-    pub async fn get_render_hint() -> Self {
-        let line = run_cmd("busctl --user get-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 DefaultHintHr").await;
-        debug!("Received line hint: {}", line);
-        let vec: Vec<&str> = line.trim().split(" ").collect();
-        let final_str = vec[1].replace("\"", "");
-        RenderHint::from_str(&final_str).unwrap()
-    }
-
-    pub async fn set(&self) {
-        let line = format!(
-            "busctl --user set-property org.pinenote.PineNoteCtl /org/pinenote/PineNoteCtl org.pinenote.Ebc1 DefaultHintHr s {}",
-            self.to_string()
-        );
-        run_cmd(&line).await;
-    }
-}
-
 #[derive(Copy, Clone, Debug, Default)]
 pub struct VisibleSettings {
     pub dithering: bool,
@@ -366,107 +347,229 @@ impl VisibleSettings {
     }
 }
 
-pub async fn set_screen_settings(
-    screen_settings: DriverMode,
-    state: &str, // gamma_channel_tx: &mut tokio::sync::mpsc::Sender<GammaControl>,
-) {
-    let current_render_hint = RenderHint::get_render_hint().await;
-    let mut render_hint = current_render_hint;
-    let mut visible_settings = VisibleSettings::default();
-    debug!("Got render hint which is: {:#?}", current_render_hint);
-    screen_settings.set().await;
-    match screen_settings {
-        DriverMode::Normal(bit_depth) => {
-            let mut maybe_conversion = None;
-            let mut maybe_redraw = None;
-            match bit_depth {
-                BitDepth::Y1(conversion, _thresholding_level) => {
-                    maybe_conversion = Some(conversion);
-                    render_hint.bit_depth = PureBitDepth::Y1;
-                    render_hint.redraw = PureRedraw::DisableFastDrawing // Doesn't make sense
-                }
-                BitDepth::Y2(conversion, redraw) => {
-                    maybe_conversion = Some(conversion);
-                    maybe_redraw = Some(redraw);
-                    render_hint.bit_depth = PureBitDepth::Y2;
-                }
-                BitDepth::Y4(redraw) => {
-                    maybe_redraw = Some(redraw);
-                    render_hint.bit_depth = PureBitDepth::Y4;
-                }
-            }
-            visible_settings.bitdepth = true;
-            if let Some(conversion) = maybe_conversion {
-                visible_settings.conversion = true;
-                match conversion {
-                    Conversion::Thresholding => {
-                        render_hint.conversion = PureConversion::Thresholding;
-                        // Only in Y1
-                        match bit_depth {
-                            BitDepth::Y1(_conversion, thresholding_level) => {
-                                visible_settings.thresholding_level = true;
-                                debug!("Setting thresholding value");
-                                thresholding_level.set().await;
-                                thresholding_level.set_eww_number().await;
-                            }
-                            _ => {}
-                        }
-                        /*
-                        gamma_channel_tx
-                            .send(GammaControl::PreviousValue)
-                            .await
-                            .ok();
-                        */
-                    }
-                    Conversion::Dithering(dithering) => {
-                        render_hint.conversion = PureConversion::Dithering;
-
-                        dithering.set().await;
-                        visible_settings.dithering = true;
-                    }
-                }
-            }
-            if let Some(redraw) = maybe_redraw {
-                visible_settings.redraw = true;
-                match redraw {
-                    Redraw::FastDrawing(delay_drawing) => {
-                        render_hint.redraw = PureRedraw::FastDrawing;
-
-                        visible_settings.redraw_level = true;
-                        delay_drawing.set().await;
-                    }
-                    Redraw::DisableFastDrawing => {
-                        render_hint.redraw = PureRedraw::DisableFastDrawing
-                    }
-                }
+// One write to the driver. A plan applies them in the order of this enum, so the
+// parameters are in place before the hint and the mode which use them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScreenStep {
+    Threshold(ThresholdLevel),
+    DitherMode(Dithering),
+    RedrawDelay(u16),
+    RenderHint(RenderHint),
+    DriverMode(PureDriverMode),
+}
+
+impl ScreenStep {
+    async fn write(&self) -> io::Result<()> {
+        match self {
+            ScreenStep::Threshold(level) => level.set().await,
+            ScreenStep::DitherMode(dithering) => dithering.set().await,
+            ScreenStep::RedrawDelay(delay) => RedrawOptions { delay: *delay }.set().await,
+            ScreenStep::RenderHint(hint) => hint.set().await,
+            ScreenStep::DriverMode(mode) => mode.set().await,
+        }
+    }
+
+    // The same step, with the value the driver has now
+    async fn read_back(&self) -> io::Result<ScreenStep> {
+        Ok(match self {
+            ScreenStep::Threshold(_) => ScreenStep::Threshold(ThresholdLevel::get().await?),
+            ScreenStep::DitherMode(_) => ScreenStep::DitherMode(read_dither_mode().await?),
+            ScreenStep::RedrawDelay(_) => ScreenStep::RedrawDelay(read_redraw_delay().await?),
+            ScreenStep::RenderHint(_) => {
+                ScreenStep::RenderHint(RenderHint::get_render_hint().await?)
             }
+            ScreenStep::DriverMode(_) => ScreenStep::DriverMode(PureDriverMode::get().await?),
+        })
+    }
+
+    // The same step with the value from a state, used to roll back
+    fn value_in(&self, state: &DriverState) -> Option<ScreenStep> {
+        match self {
+            ScreenStep::Threshold(_) => Some(ScreenStep::Threshold(state.y1_threshold)),
+            ScreenStep::DitherMode(_) => state.dither_mode.map(ScreenStep::DitherMode),
+            ScreenStep::RedrawDelay(_) => state.redraw_delay.map(ScreenStep::RedrawDelay),
+            ScreenStep::RenderHint(_) => state.hint.map(ScreenStep::RenderHint),
+            ScreenStep::DriverMode(_) => Some(ScreenStep::DriverMode(state.driver_mode)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApplyError {
+    // None when the current state could not be read, then nothing was written
+    pub step: Option<ScreenStep>,
+    pub error: String,
+    pub rolled_back: bool,
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(step) = &self.step else {
+            return write!(
+                f,
+                "Reading the driver state failed: {} (nothing was changed)",
+                self.error
+            );
+        };
+        write!(f, "Step {:?} failed: {}", step, self.error)?;
+        if self.rolled_back {
+            write!(f, " (rolled back)")
+        } else {
+            write!(f, " (the driver may be in a mixed state)")
+        }
+    }
+}
+
+pub struct ScreenPlan {
+    pub current: DriverState,
+    pub steps: Vec<ScreenStep>,
+}
+
+impl ScreenPlan {
+    // Only the values which differ from the current state are written
+    pub fn new(current: DriverState, target: &DriverState) -> Self {
+        let mut steps = Vec::new();
+        if target.y1_threshold != current.y1_threshold {
+            steps.push(ScreenStep::Threshold(target.y1_threshold));
         }
-        DriverMode::Fast(dithering) => {
-            dithering.set().await;
-            visible_settings.dithering = true;
+        if let Some(dithering) = target
+            .dither_mode
+            .filter(|d| Some(*d) != current.dither_mode)
+        {
+            steps.push(ScreenStep::DitherMode(dithering));
         }
+        if let Some(delay) = target
+            .redraw_delay
+            .filter(|d| Some(*d) != current.redraw_delay)
+        {
+            steps.push(ScreenStep::RedrawDelay(delay));
+        }
+        if let Some(hint) = target.hint.filter(|h| Some(*h) != current.hint) {
+            steps.push(ScreenStep::RenderHint(hint));
+        }
+        if target.driver_mode != current.driver_mode {
+            steps.push(ScreenStep::DriverMode(target.driver_mode));
+        }
+        ScreenPlan { current, steps }
     }
 
-    if !visible_settings.thresholding_level {
-        debug!("Setting default treshold level");
-        let thresholding_level = ThresholdLevel::default();
-        thresholding_level.set().await;
-        thresholding_level.set_eww_number().await; // So when it's visible again, it's the good number
-
-        /*
-        gamma_channel_tx
-            .send(GammaControl::Force(DEFAULT_GAMMA))
-            .await
-            .ok();
-         */
+    pub async fn apply(&self) -> Result<(), ApplyError> {
+        for (i, step) in self.steps.iter().enumerate() {
+            debug!("Applying screen step {:?}", step);
+            let result = match step.write().await {
+                Ok(()) => match step.read_back().await {
+                    Ok(read) if read == *step => Ok(()),
+                    Ok(read) => Err(format!("Driver reports {:?} after the write", read)),
+                    Err(e) => Err(format!("Failed to read back: {}", e)),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(error) = result {
+                // The failed step is rolled back too, it may have been half written
+                let rolled_back = self.rollback(&self.steps[..=i]).await;
+                return Err(ApplyError {
+                    step: Some(*step),
+                    error,
+                    rolled_back,
+                });
+            }
+        }
+        Ok(())
     }
-    if render_hint != current_render_hint {
-        debug!("Render hint changed! It's now: {:#?}", render_hint);
-        render_hint.set().await;
+
+    async fn rollback(&self, steps: &[ScreenStep]) -> bool {
+        let mut rolled_back = true;
+        for step in steps.iter().rev() {
+            let Some(previous) = step.value_in(&self.current) else {
+                continue;
+            };
+            warn!("Rolling back to {:?}", previous);
+            if let Err(e) = previous.write().await {
+                error!("Failed to roll back {:?}: {}", previous, e);
+                rolled_back = false;
+            }
+        }
+        rolled_back
     }
+}
 
-    visible_settings.set(state).await;
+fn visible_settings(mode: &DriverMode) -> VisibleSettings {
+    let mut visible = VisibleSettings::default();
+    match mode {
+        DriverMode::Fast(_) => visible.dithering = true,
+        DriverMode::Normal(bit_depth) => {
+            visible.bitdepth = true;
+            let (conversion, redraw) = match bit_depth {
+                BitDepth::Y1(conversion, _) => {
+                    visible.thresholding_level = *conversion == Conversion::Thresholding;
+                    (Some(conversion), None)
+                }
+                BitDepth::Y2(conversion, redraw) => (Some(conversion), Some(redraw)),
+                BitDepth::Y4(redraw) => (None, Some(redraw)),
+            };
+            if let Some(conversion) = conversion {
+                visible.conversion = true;
+                visible.dithering = matches!(conversion, Conversion::Dithering(_));
+            }
+            if let Some(redraw) = redraw {
+                visible.redraw = true;
+                visible.redraw_level = matches!(redraw, Redraw::FastDrawing(_));
+            }
+        }
+    }
+    visible
+}
+
+// Makes the panel show a mode, whoever set it
+pub async fn sync_eww_panel(mode: &DriverMode, state: &str, sliders: &SliderConfig) {
+    let current = EwwScreenConfig::get_eww_screen_config(state, sliders).await;
+    current
+        .with_driver_mode(mode, sliders)
+        .update_eww(&current)
+        .await;
+    // So when it's visible again, it's the good number
+    DriverState::from_driver_mode(mode)
+        .y1_threshold
+        .set_eww_number()
+        .await;
+    visible_settings(mode).set(state).await;
+}
+
+async fn read_current_state() -> Result<DriverState, ApplyError> {
+    DriverState::read().await.map_err(|e| ApplyError {
+        step: None,
+        error: e.to_string(),
+        rolled_back: false,
+    })
+}
+
+// Only the driver wide values of a mode, with per window settings the compositor sets the
+// render hint of every window itself
+pub async fn set_global_settings(mode: &DriverMode) -> Result<(), ApplyError> {
+    let current = read_current_state().await?;
+    let target = DriverState {
+        hint: None,
+        ..DriverState::from_driver_mode(mode)
+    };
+    let plan = ScreenPlan::new(current, &target);
+    debug!("Global settings plan: {:#?}", plan.steps);
+    plan.apply().await
+}
+
+pub async fn set_screen_settings(
+    screen_settings: DriverMode,
+    state: &str,
+    sliders: &SliderConfig,
+) -> Result<(), ApplyError> {
+    let current = read_current_state().await?;
+    let target = DriverState::from_driver_mode(&screen_settings);
+    let plan = ScreenPlan::new(current, &target);
+    debug!("Screen plan: {:#?}", plan.steps);
+    plan.apply().await?;
+
+    sync_eww_panel(&screen_settings, state, sliders).await;
     // refresh_screen().await;
 
     debug!("Set screen settings finished!");
+    Ok(())
 }
@@ -1,79 +1,688 @@
-use enums::Requests;
-use log::{debug, error, info};
-use quill_data_provider_lib::{BitDepth, Conversion, DriverMode, Redraw, run_cmd};
-use std::time::Duration;
-use tokio::time::sleep;
+use async_trait::async_trait;
+use enums::{OverrideUntil, Requests};
+use log::{debug, error, info, warn};
+use quill_data_provider_lib::driver::{DriverState, RenderHint};
+use quill_data_provider_lib::{
+    DriverMode, EinkPreset, EinkWindowSetting, GammaLevel, PRESETS_CONFIG_NAME, RefreshPolicy,
+    WINDOW_SETTINGS_CONFIG_NAME, home_config_path, read_presets, read_window_settings, run_cmd,
+    write_window_settings,
+};
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::{
+    sync::{mpsc, watch},
+    time::sleep,
+};
 
+use crate::config::{ProviderConfig, SliderConfig};
 use crate::eink::{
-    EwwScreenConfig, eww_screen_config_to_enum, refresh_screen, set_screen_settings,
+    ApplyError, EwwScreenConfig, eww_screen_config_to_enum, set_global_settings,
+    set_screen_settings, sync_eww_panel,
 };
+use crate::focus::FocusedWindow;
+use crate::gamma::GammaControl;
+use crate::listener::SocketHandler;
+use crate::pen::{PenConfig, PenState};
+use crate::refresh::RefreshReason;
 
 pub struct EinkListener {
     pub channel_rx: tokio::sync::broadcast::Receiver<Requests>,
     pub window_settings: bool,
-    // pub gamma_channel_tx: tokio::sync::mpsc::Sender<GammaControl>,
+    pub presets: Vec<EinkPreset>,
+    // None when the settings came from eww and not from a preset
+    pub active_preset: Option<usize>,
+    pub preset_tx: tokio::sync::watch::Sender<String>,
+    pub current_mode: DriverMode,
+    pub mode_override: Option<ModeOverride>,
+    pub focus_rx: watch::Receiver<FocusedWindow>,
+    pub input_rx: watch::Receiver<Instant>,
+    pub refresh_tx: mpsc::Sender<RefreshReason>,
+    pub refresh_policy_tx: watch::Sender<RefreshPolicy>,
+    // From the last time settings were applied, published with the preset
+    pub last_error: Option<String>,
+    // Follow config reloads, like the panel does through the sliders topic
+    pub sliders: SliderConfig,
+    pub config_rx: watch::Receiver<ProviderConfig>,
+    pub gamma_channel_tx: mpsc::Sender<GammaControl>,
+    // From a preset or window setting, None is the gamma set with requests
+    pub gamma: Option<GammaLevel>,
+    pub pen: PenConfig,
+    pub pen_rx: watch::Receiver<PenState>,
+    pub windows: Vec<EinkWindowSetting>,
+    // Of the window settings file when it was read, it's only read again once it changes
+    pub windows_modified: Option<SystemTime>,
+    // The window whose settings the driver has, None for the default ones
+    pub focused_app: Option<String>,
+}
+
+// After the switch back from an override failed
+const OVERRIDE_RETRY_SECONDS: u32 = 5;
+
+// What to go back to once a temporary mode is over
+#[derive(Debug)]
+pub struct ModeOverride {
+    pub until: OverrideUntil,
+    pub started: Instant,
+    pub previous_mode: DriverMode,
+    pub previous_preset: Option<usize>,
+    pub previous_hint: Option<RenderHint>,
+    pub previous_refresh: RefreshPolicy,
+    pub previous_gamma: Option<GammaLevel>,
+}
+
+// Resolves when the override should be reverted, never for focus changes, those come from focus_rx
+async fn override_expired(
+    mode_override: Option<&ModeOverride>,
+    input_rx: &watch::Receiver<Instant>,
+    mut pen_rx: watch::Receiver<PenState>,
+) {
+    let Some(mode_override) = mode_override else {
+        return std::future::pending().await;
+    };
+    match mode_override.until {
+        OverrideUntil::Seconds(seconds) => {
+            tokio::time::sleep_until(
+                (mode_override.started + Duration::from_secs(seconds as u64)).into(),
+            )
+            .await;
+        }
+        OverrideUntil::InputIdle(ms) => loop {
+            let last_input = (*input_rx.borrow()).max(mode_override.started);
+            let due = last_input + Duration::from_millis(ms as u64);
+            if Instant::now() >= due {
+                break;
+            }
+            tokio::time::sleep_until(due.into()).await;
+        },
+        OverrideUntil::PenAway(ms) => loop {
+            if pen_rx.borrow_and_update().in_proximity {
+                if pen_rx.changed().await.is_err() {
+                    return std::future::pending().await;
+                }
+                continue;
+            }
+            tokio::select! {
+                _ = sleep(Duration::from_millis(ms as u64)) => break,
+                res = pen_rx.changed() => {
+                    if res.is_err() {
+                        break;
+                    }
+                }
+            }
+        },
+        OverrideUntil::FocusChange => std::future::pending().await,
+    }
 }
 
-pub async fn default_set_screen_settings() {
-    // Perfect defaults, middle ground between speed and look
-    set_screen_settings(
-        DriverMode::Normal(BitDepth::Y2(
-            Conversion::Thresholding,
-            Redraw::DisableFastDrawing,
-        )),
-        // &mut self.gamma_channel_tx,
-        &run_cmd("eww --no-daemonize state").await,
-    )
-    .await;
+#[derive(Debug, Serialize)]
+struct PresetInfo<'a> {
+    active: &'a str,
+    presets: Vec<&'a str>,
+    error: Option<&'a str>,
 }
 
 impl EinkListener {
     pub async fn start(&mut self) {
         info!("Starting EinkListener");
+        self.reload_presets();
         debug!("Setting initial settings");
-        default_set_screen_settings().await;
+        self.adopt_screen_settings().await;
         loop {
-            if let Ok(data) = self.channel_rx.recv().await {
-                match data {
-                    Requests::ScreenRefresh => {
-                        refresh_screen().await;
+            tokio::select! {
+                res = self.channel_rx.recv() => {
+                    if let Ok(data) = res {
+                        self.handle_request(data).await;
+                    } else {
+                        error!("Failed to recv");
+                        sleep(Duration::from_secs(1)).await;
                     }
-                    Requests::ScreenSettings => {
-                        self.screen_settings_call(false).await;
+                }
+                _ = override_expired(
+                    self.mode_override.as_ref(),
+                    &self.input_rx,
+                    self.pen_rx.clone(),
+                ) => {
+                    self.revert_override().await;
+                }
+                Ok(()) = self.config_rx.changed() => {
+                    let config = self.config_rx.borrow_and_update();
+                    self.sliders = config.sliders.clone();
+                    self.pen = config.pen.clone();
+                }
+                Ok(()) = self.pen_rx.changed() => {
+                    let in_proximity = self.pen_rx.borrow_and_update().in_proximity;
+                    self.pen_changed(in_proximity).await;
+                }
+                Ok(()) = self.focus_rx.changed() => {
+                    if self
+                        .mode_override
+                        .as_ref()
+                        .is_some_and(|o| o.until == OverrideUntil::FocusChange)
+                    {
+                        self.revert_override().await;
                     }
-                    Requests::SmallScreenSettings => {
-                        self.screen_settings_call(true).await;
+                    if self.window_settings {
+                        if self.mode_override.is_some() {
+                            self.override_follows_focus();
+                        } else {
+                            self.apply_window_globals().await;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_request(&mut self, data: Requests) {
+        match data {
+            Requests::ScreenRefresh => {
+                self.request_refresh(RefreshReason::Request).await;
+            }
+            Requests::ScreenSettings => {
+                self.mode_override = None;
+                self.screen_settings_call(false).await;
+            }
+            Requests::SmallScreenSettings => {
+                self.mode_override = None;
+                self.screen_settings_call(true).await;
+            }
+            Requests::ApplyPreset(name) => {
+                self.reload_presets();
+                match self.presets.iter().position(|p| p.name == name) {
+                    Some(index) => {
+                        self.mode_override = None;
+                        self.apply_preset(index).await;
                     }
-                    _ => {}
+                    None => warn!("No preset named {}", name),
                 }
-            } else {
-                error!("Failed to recv");
-                sleep(Duration::from_secs(1)).await;
             }
+            Requests::NextPreset => {
+                self.mode_override = None;
+                self.cycle_preset(true).await;
+            }
+            Requests::PreviousPreset => {
+                self.mode_override = None;
+                self.cycle_preset(false).await;
+            }
+            Requests::OverrideMode(mode, until) => {
+                self.override_mode(&mode, until).await;
+            }
+            Requests::ReloadConfig => {
+                self.windows_modified = None;
+                self.reload_window_settings();
+                self.reload_presets();
+            }
+            _ => {}
+        }
+    }
+
+    // A preset name, or a DriverMode written in ron which then uses the default refresh policy
+    // and keeps the gamma
+    fn resolve_mode(
+        &mut self,
+        mode: &str,
+    ) -> Option<(DriverMode, RefreshPolicy, Option<GammaLevel>)> {
+        self.reload_presets();
+        if let Some(preset) = self.presets.iter().find(|p| p.name == mode) {
+            return Some((preset.settings, preset.refresh, Some(preset.gamma)));
+        }
+        match ron::from_str(mode) {
+            Ok(mode) => Some((mode, self.default_refresh(), None)),
+            Err(e) => {
+                warn!("{} is not a preset nor a driver mode: {}", mode, e);
+                None
+            }
+        }
+    }
+
+    async fn override_mode(&mut self, mode: &str, until: OverrideUntil) {
+        let Some((new_mode, refresh, gamma)) = self.resolve_mode(mode) else {
+            return;
+        };
+        info!("Overriding mode with {:?} until {:?}", new_mode, until);
+        let started = Instant::now();
+        // Overriding an override only moves the end, we still go back to the original settings
+        let new_override = self.mode_override.is_none();
+        match self.mode_override.as_mut() {
+            Some(mode_override) => {
+                mode_override.until = until;
+                mode_override.started = started;
+            }
+            None => {
+                self.mode_override = Some(ModeOverride {
+                    until,
+                    started,
+                    previous_mode: self.current_mode,
+                    previous_preset: self.active_preset,
+                    previous_hint: RenderHint::get_render_hint()
+                        .await
+                        .inspect_err(|e| warn!("Can't restore the render hint later: {}", e))
+                        .ok(),
+                    previous_refresh: *self.refresh_policy_tx.borrow(),
+                    previous_gamma: self.gamma,
+                });
+            }
+        }
+        let result = self
+            .set_mode(
+                new_mode,
+                refresh,
+                &run_cmd("eww --no-daemonize state").await,
+            )
+            .await;
+        if result.is_err() && new_override {
+            // Nothing changed, so there is nothing to go back to
+            self.mode_override = None;
+        } else if result.is_ok() {
+            self.active_preset = None;
+            if gamma.is_some() {
+                self.set_gamma(gamma).await;
+            }
+        }
+        self.publish_preset();
+    }
+
+    // The drawing preset comes in as an override which ends once the pen is away, it doesn't
+    // replace an override which is already running
+    async fn pen_changed(&mut self, in_proximity: bool) {
+        if !in_proximity || self.mode_override.is_some() {
+            return;
+        }
+        if let Some(preset) = self.pen.drawing_preset.clone() {
+            debug!("Pen in proximity, switching to {}", preset);
+            self.override_mode(&preset, OverrideUntil::PenAway(self.pen.away_ms))
+                .await;
+        }
+    }
+
+    // An override outlasting a focus change, like the pen's, ends with the settings of the
+    // window which has focus then
+    fn override_follows_focus(&mut self) {
+        let (app_id, mode, gamma) = self.focused_window_settings();
+        let refresh = self.default_refresh();
+        if let Some(mode_override) = self.mode_override.as_mut() {
+            debug!("Override now goes back to {:?}", mode);
+            mode_override.previous_mode = mode;
+            mode_override.previous_preset = None;
+            mode_override.previous_refresh = refresh;
+            mode_override.previous_gamma = Some(gamma);
+        }
+        // The driver holds the override, so the window we left has nothing to save
+        self.focused_app = app_id;
+    }
+
+    async fn revert_override(&mut self) {
+        let Some(mode_override) = self.mode_override.take() else {
+            return;
+        };
+        info!(
+            "Override is over, going back to {:?}",
+            mode_override.previous_mode
+        );
+        let Ok(refreshed) = self
+            .set_mode(
+                mode_override.previous_mode,
+                mode_override.previous_refresh,
+                &run_cmd("eww --no-daemonize state").await,
+            )
+            .await
+        else {
+            // Kept to try again in a bit, whatever ended it may not come again
+            warn!(
+                "Retrying the end of the override in {}s",
+                OVERRIDE_RETRY_SECONDS
+            );
+            self.mode_override = Some(ModeOverride {
+                until: OverrideUntil::Seconds(OVERRIDE_RETRY_SECONDS),
+                started: Instant::now(),
+                ..mode_override
+            });
+            self.publish_preset();
+            return;
+        };
+        if let Some(previous_hint) = mode_override.previous_hint
+            && RenderHint::get_render_hint().await.ok() != Some(previous_hint)
+            && let Err(e) = previous_hint.set().await
+        {
+            error!("Failed to restore the render hint: {}", e);
+        }
+        self.set_gamma(mode_override.previous_gamma).await;
+        self.active_preset = mode_override.previous_preset;
+        self.publish_preset();
+        // The quick mode leaves ghosting behind
+        if !refreshed {
+            self.request_refresh(RefreshReason::OverrideEnd).await;
+        }
+    }
+
+    // Returns if a global refresh was requested because of the switch. Errors are logged
+    // and kept for publishing here, callers only need to know the mode did not change
+    async fn set_mode(
+        &mut self,
+        mode: DriverMode,
+        refresh: RefreshPolicy,
+        state: &str,
+    ) -> Result<bool, ApplyError> {
+        if let Err(e) = set_screen_settings(mode, state, &self.sliders).await {
+            error!("Failed to set screen settings to {:?}: {}", mode, e);
+            self.last_error = Some(e.to_string());
+            return Err(e);
+        }
+        self.last_error = None;
+        Ok(self.mode_changed(mode, refresh).await)
+    }
+
+    // Returns if a global refresh was requested because of the switch
+    async fn mode_changed(&mut self, mode: DriverMode, refresh: RefreshPolicy) -> bool {
+        let fast_to_normal = matches!(self.current_mode, DriverMode::Fast(_))
+            && matches!(mode, DriverMode::Normal(_));
+        self.current_mode = mode;
+        self.refresh_policy_tx.send_if_modified(|current| {
+            let changed = *current != refresh;
+            *current = refresh;
+            changed
+        });
+        if fast_to_normal && refresh.on_fast_to_normal {
+            self.request_refresh(RefreshReason::FastToNormal).await;
+            return true;
+        }
+        false
+    }
+
+    // With per window settings the threshold, dithering, redraw delay and driver mode follow
+    // the focused window, they are driver wide. Windows without settings get the default ones
+    async fn apply_window_globals(&mut self) {
+        self.save_window_globals().await;
+        let (app_id, mode, gamma) = self.focused_window_settings();
+        debug!("Global settings for the focused window: {:?}", mode);
+        match set_global_settings(&mode).await {
+            Ok(()) => {
+                self.last_error = None;
+                self.mode_changed(mode, self.default_refresh()).await;
+                self.set_gamma(Some(gamma)).await;
+                self.active_preset = None;
+                self.focused_app = app_id;
+            }
+            Err(e) => {
+                error!("Failed to set global settings to {:?}: {}", mode, e);
+                self.last_error = Some(e.to_string());
+            }
+        }
+        self.publish_preset();
+    }
+
+    // The app id is only there for a window with settings
+    fn focused_window_settings(&mut self) -> (Option<String>, DriverMode, GammaLevel) {
+        self.reload_window_settings();
+        let app_id = self.focus_rx.borrow().app_id.clone();
+        match app_id.and_then(|app_id| self.windows.iter().find(|s| s.app_id == app_id)) {
+            Some(window) => (Some(window.app_id.clone()), window.settings, window.gamma),
+            None => (None, self.default_mode(), self.default_gamma()),
         }
     }
 
+    // What changed while the window had focus, like the threshold from the panel, is kept in
+    // its settings. Windows without settings share the default ones, those aren't saved
+    async fn save_window_globals(&mut self) {
+        let Some(app_id) = self.focused_app.take() else {
+            return;
+        };
+        let current = match DriverState::read().await {
+            Ok(current) => current,
+            Err(e) => {
+                warn!("Can't save the settings of {}: {}", app_id, e);
+                return;
+            }
+        };
+        self.reload_window_settings();
+        let Some(window) = self.windows.iter_mut().find(|w| w.app_id == app_id) else {
+            return;
+        };
+        let Some(mode) = DriverState::from_driver_mode(&window.settings)
+            .with_globals_from(&current)
+            .to_driver_mode()
+        else {
+            return;
+        };
+        if mode == window.settings {
+            return;
+        }
+        info!("Saving {:?} for {}", mode, app_id);
+        window.settings = mode;
+        let path = home_config_path(WINDOW_SETTINGS_CONFIG_NAME);
+        match write_window_settings(&path, &self.windows) {
+            Ok(()) => self.windows_modified = modified(&path),
+            Err(e) => error!("Failed to save the window settings to {}: {}", path, e),
+        }
+    }
+
+    // Read again only when the file changed, the GUI saves it while we run. A file which can't
+    // be read keeps the settings we have
+    fn reload_window_settings(&mut self) {
+        let path = home_config_path(WINDOW_SETTINGS_CONFIG_NAME);
+        let modified = modified(&path);
+        if modified.is_some() && modified == self.windows_modified {
+            return;
+        }
+        match read_window_settings(&path) {
+            Ok(windows) => {
+                debug!("Window settings: {:#?}", windows);
+                self.windows = windows;
+                self.windows_modified = modified;
+            }
+            Err(e) => warn!("Keeping the current window settings: {}", e),
+        }
+    }
+
+    async fn set_gamma(&mut self, gamma: Option<GammaLevel>) {
+        self.gamma = gamma;
+        let control = match gamma {
+            Some(gamma) => GammaControl::Force(gamma.tenths),
+            None => GammaControl::PreviousValue,
+        };
+        if let Err(e) = self.gamma_channel_tx.send(control).await {
+            error!("Failed to send the gamma: {}", e);
+        }
+    }
+
+    async fn request_refresh(&self, reason: RefreshReason) {
+        if let Err(e) = self.refresh_tx.send(reason).await {
+            error!("Failed to request a refresh: {}", e);
+        }
+    }
+
+    fn default_mode(&self) -> DriverMode {
+        self.presets.first().map(|p| p.settings).unwrap_or_default()
+    }
+
+    fn default_gamma(&self) -> GammaLevel {
+        self.presets.first().map(|p| p.gamma).unwrap_or_default()
+    }
+
+    fn default_refresh(&self) -> RefreshPolicy {
+        self.presets.first().map(|p| p.refresh).unwrap_or_default()
+    }
+
     async fn screen_settings_call(&mut self, _quick: bool) {
         debug!("Got screen settings call");
         let state = &run_cmd("eww --no-daemonize state").await;
-        let screen_settings = EwwScreenConfig::get_eww_screen_config(state).await;
+        let screen_settings = EwwScreenConfig::get_eww_screen_config(state, &self.sliders).await;
         debug!("Screen settings: {:?}", screen_settings);
         if self.window_settings != screen_settings.window_settings {
             self.window_settings = screen_settings.window_settings;
+            // The panel's settings aren't any window's
+            self.focused_app = None;
             if self.window_settings {
-                default_set_screen_settings().await;
+                self.default_set_screen_settings().await;
             }
             screen_settings.set_window_settings().await;
+            if self.window_settings {
+                self.apply_window_globals().await;
+            }
         }
         if !self.window_settings {
-            let enum_screen_settings = eww_screen_config_to_enum(&screen_settings).await;
+            let enum_screen_settings =
+                eww_screen_config_to_enum(&screen_settings, &self.sliders).await;
             debug!("Enum screen settings: {:#?}", enum_screen_settings);
-            set_screen_settings(
-                enum_screen_settings,
-                state, // &mut self.gamma_channel_tx
-                       // quick
-            )
+            let result = self
+                .set_mode(
+                    enum_screen_settings,
+                    self.default_refresh(),
+                    state, // quick
+                )
+                .await;
+            if result.is_ok() {
+                self.active_preset = None;
+                self.set_gamma(None).await;
+            }
+            self.publish_preset();
+        }
+    }
+
+    // Presets are read again on every preset call, so changes from the GUI apply without a restart.
+    // A file which can't be read keeps the presets we have
+    fn reload_presets(&mut self) {
+        let presets = match read_presets(&home_config_path(PRESETS_CONFIG_NAME)) {
+            Ok(presets) => presets,
+            Err(e) => {
+                warn!("Keeping the current presets: {}", e);
+                return;
+            }
+        };
+        if presets != self.presets {
+            debug!("Presets changed: {:#?}", presets);
+            self.active_preset = self
+                .active_preset
+                .and_then(|i| self.presets.get(i))
+                .and_then(|old| presets.iter().position(|p| p.name == old.name));
+            self.presets = presets;
+            self.publish_preset();
+        }
+    }
+
+    // Keeps what the driver already runs, like after a restart of the provider. The default
+    // preset is only forced when the driver can't be read
+    async fn adopt_screen_settings(&mut self) {
+        let mode = match DriverMode::get().await {
+            Ok(mode) => mode,
+            Err(e) => {
+                warn!("Failed to read the driver mode, using the default: {}", e);
+                self.default_set_screen_settings().await;
+                return;
+            }
+        };
+        info!("Adopting the current driver mode {:?}", mode);
+        // Compared as driver states, values the mode doesn't use don't matter
+        let state = DriverState::from_driver_mode(&mode);
+        self.active_preset = self
+            .presets
+            .iter()
+            .position(|p| DriverState::from_driver_mode(&p.settings) == state);
+        let refresh = self
+            .active_preset
+            .map(|i| self.presets[i].refresh)
+            .unwrap_or_else(|| self.default_refresh());
+        self.current_mode = mode;
+        self.refresh_policy_tx.send_replace(refresh);
+        if let Some(index) = self.active_preset {
+            self.set_gamma(Some(self.presets[index].gamma)).await;
+        }
+        sync_eww_panel(
+            &mode,
+            &run_cmd("eww --no-daemonize state").await,
+            &self.sliders,
+        )
+        .await;
+        self.publish_preset();
+    }
+
+    // The first preset is the default one, middle ground between speed and look
+    async fn default_set_screen_settings(&mut self) {
+        if self.presets.is_empty() {
+            warn!("No presets, using the built in default");
+            let result = self
+                .set_mode(
+                    DriverMode::default(),
+                    RefreshPolicy::default(),
+                    &run_cmd("eww --no-daemonize state").await,
+                )
+                .await;
+            if result.is_ok() {
+                self.active_preset = None;
+                self.set_gamma(None).await;
+            }
+            self.publish_preset();
+        } else {
+            self.apply_preset(0).await;
+        }
+    }
+
+    async fn apply_preset(&mut self, index: usize) {
+        let Some(preset) = self.presets.get(index) else {
+            error!("Preset index {} out of range", index);
+            return;
+        };
+        info!("Applying preset {}", preset.name);
+        let (mode, refresh, gamma) = (preset.settings, preset.refresh, preset.gamma);
+        let result = self
+            .set_mode(mode, refresh, &run_cmd("eww --no-daemonize state").await)
             .await;
+        if result.is_ok() {
+            self.active_preset = Some(index);
+            self.set_gamma(Some(gamma)).await;
+        }
+        self.publish_preset();
+    }
+
+    async fn cycle_preset(&mut self, forward: bool) {
+        self.reload_presets();
+        let len = self.presets.len();
+        if len == 0 {
+            warn!("No presets to cycle through");
+            return;
+        }
+        let index = match self.active_preset {
+            Some(i) if forward => (i + 1) % len,
+            Some(i) => (i + len - 1) % len,
+            None => 0,
+        };
+        self.apply_preset(index).await;
+    }
+
+    fn publish_preset(&self) {
+        let info = PresetInfo {
+            active: self
+                .active_preset
+                .and_then(|i| self.presets.get(i))
+                .map(|p| p.name.as_str())
+                .unwrap_or(""),
+            presets: self.presets.iter().map(|p| p.name.as_str()).collect(),
+            error: self.last_error.as_deref(),
+        };
+        match serde_json::to_string(&info) {
+            Ok(json) => {
+                self.preset_tx.send_replace(json);
+            }
+            Err(e) => error!("Failed to serialize preset info: {}", e),
         }
     }
 }
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub struct EinkPresetListener {
+    pub preset_rx: tokio::sync::watch::Receiver<String>,
+}
+
+#[async_trait]
+impl SocketHandler for EinkPresetListener {
+    const SOCKET_NAME: &'static str = "eink_preset";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting EinkPresetListener");
+        let mut preset_rx = self.preset_rx.clone();
+        self.forward_watch(unix, &mut preset_rx).await;
+    }
+}
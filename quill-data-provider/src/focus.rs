@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use log::{debug, error, info, warn};
+use serde_json::Value;
+
+use crate::process::ManagedProcess;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FocusedWindow {
+    pub id: Option<u64>,
+    pub app_id: Option<String>,
+}
+
+// Follows niri's event stream, and keeps an id -> app_id map so focus changes can be resolved
+pub struct FocusListener {
+    pub focus_tx: tokio::sync::watch::Sender<FocusedWindow>,
+    pub windows: HashMap<u64, Option<String>>,
+}
+
+fn window_from_json(window: &Value) -> Option<(u64, Option<String>, bool)> {
+    let id = window.get("id")?.as_u64()?;
+    let app_id = window
+        .get("app_id")
+        .and_then(|a| a.as_str())
+        .map(|a| a.to_string());
+    let is_focused = window
+        .get("is_focused")
+        .and_then(|f| f.as_bool())
+        .unwrap_or(false);
+    Some((id, app_id, is_focused))
+}
+
+impl FocusListener {
+    pub async fn start(&mut self) {
+        info!("Starting FocusListener");
+        // A restarted stream begins with WindowsChanged, so the map is rebuilt
+        let mut event_stream = ManagedProcess::new("niri")
+            .args(["msg", "--json", "event-stream"])
+            .forward_stdout()
+            .spawn();
+
+        while let Some(line) = event_stream.next_line().await {
+            let Ok(event) = serde_json::from_str::<Value>(&line) else {
+                warn!("Failed to parse niri event: {}", line);
+                continue;
+            };
+            self.handle_event(&event);
+        }
+        error!("niri event stream ended");
+    }
+
+    fn handle_event(&mut self, event: &Value) {
+        if let Some(changed) = event.get("WindowsChanged") {
+            self.windows.clear();
+            let mut focused = None;
+            for window in changed["windows"].as_array().into_iter().flatten() {
+                if let Some((id, app_id, is_focused)) = window_from_json(window) {
+                    self.windows.insert(id, app_id);
+                    if is_focused {
+                        focused = Some(id);
+                    }
+                }
+            }
+            self.set_focus(focused);
+        } else if let Some(changed) = event.get("WindowOpenedOrChanged") {
+            if let Some((id, app_id, is_focused)) = window_from_json(&changed["window"]) {
+                self.windows.insert(id, app_id);
+                if is_focused {
+                    self.set_focus(Some(id));
+                }
+            }
+        } else if let Some(closed) = event.get("WindowClosed") {
+            if let Some(id) = closed["id"].as_u64() {
+                self.windows.remove(&id);
+            }
+        } else if let Some(changed) = event.get("WindowFocusChanged") {
+            self.set_focus(changed["id"].as_u64());
+        }
+    }
+
+    fn set_focus(&mut self, id: Option<u64>) {
+        let focused = FocusedWindow {
+            id,
+            app_id: id.and_then(|id| self.windows.get(&id).cloned().flatten()),
+        };
+        // Title changes also come as WindowOpenedOrChanged, those are not focus changes
+        self.focus_tx.send_if_modified(|current| {
+            if *current == focused {
+                return false;
+            }
+            debug!("Focus changed to: {:?}", focused);
+            *current = focused;
+            true
+        });
+    }
+}
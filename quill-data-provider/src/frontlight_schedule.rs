@@ -0,0 +1,540 @@
+// Frontlight brightness and warmth by the time of day. Every point fades in over the
+// transition, a manual change holds until the next point
+
+use std::{
+    f64::consts::PI,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use enums::{FrontlightChange, Requests};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
+
+use crate::backlight::BacklightControl;
+
+const TICK: Duration = Duration::from_secs(30);
+const DAY: i64 = 24 * 3600;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScheduleTime {
+    // Hour and minute, local time
+    At(u8, u8),
+    // Minutes before (negative) or after the sun rises or sets, needs the coordinates
+    Sunrise(i32),
+    Sunset(i32),
+}
+
+// Percent, an axis which isn't set keeps what the points before set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SchedulePoint {
+    pub time: ScheduleTime,
+    pub brightness: Option<u8>,
+    pub temperature: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    pub enabled: bool,
+    // Degrees, north and east are positive
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub transition_minutes: u32,
+    pub points: Vec<SchedulePoint>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            latitude: None,
+            longitude: None,
+            transition_minutes: 30,
+            points: vec![
+                SchedulePoint {
+                    time: ScheduleTime::At(7, 0),
+                    brightness: None,
+                    temperature: Some(20),
+                },
+                SchedulePoint {
+                    time: ScheduleTime::At(19, 0),
+                    brightness: None,
+                    temperature: Some(70),
+                },
+                SchedulePoint {
+                    time: ScheduleTime::At(22, 0),
+                    brightness: Some(30),
+                    temperature: Some(100),
+                },
+            ],
+        }
+    }
+}
+
+// Sunrise and sunset in minutes after midnight UTC, with NOAA's approximation. None when
+// the sun doesn't rise or set that day
+pub fn sun_times(day_of_year: u32, latitude: f64, longitude: f64) -> Option<(f64, f64)> {
+    let gamma = 2.0 * PI / 365.0 * (day_of_year as f64 - 1.0);
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let latitude = latitude.to_radians();
+    // The sun's center 0.833 degrees below the horizon, for refraction and its size
+    let cos_hour_angle = 90.833f64.to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    Some((
+        720.0 - 4.0 * (longitude + hour_angle) - equation_of_time,
+        720.0 - 4.0 * (longitude - hour_angle) - equation_of_time,
+    ))
+}
+
+// Local midnight, the day of the year from 1 and the UTC offset in seconds
+fn local_day(unix: i64) -> Option<(i64, u32, i64)> {
+    let time = unix as libc::time_t;
+    // Safety: localtime_r only writes to tm
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        tm
+    };
+    let since_midnight = tm.tm_hour as i64 * 3600 + tm.tm_min as i64 * 60 + tm.tm_sec as i64;
+    Some((
+        unix - since_midnight,
+        tm.tm_yday as u32 + 1,
+        tm.tm_gmtoff as i64,
+    ))
+}
+
+impl ScheduleConfig {
+    // When a point happens on the day starting at midnight
+    fn point_time(&self, time: ScheduleTime, midnight: i64, day: u32, offset: i64) -> Option<i64> {
+        let sun = |sunset: bool| {
+            let (sunrise, sunset_time) = sun_times(day, self.latitude?, self.longitude?)?;
+            let minutes = if sunset { sunset_time } else { sunrise };
+            Some(midnight + offset + (minutes * 60.0) as i64)
+        };
+        match time {
+            ScheduleTime::At(hour, minute) => {
+                Some(midnight + hour.min(23) as i64 * 3600 + minute.min(59) as i64 * 60)
+            }
+            ScheduleTime::Sunrise(shift) => sun(false).map(|t| t + shift as i64 * 60),
+            ScheduleTime::Sunset(shift) => sun(true).map(|t| t + shift as i64 * 60),
+        }
+    }
+
+    // The points of yesterday, today and tomorrow, in order
+    fn timeline(&self, now: i64) -> Vec<(i64, &SchedulePoint)> {
+        let mut timeline = Vec::new();
+        for day_shift in [-1, 0, 1] {
+            let Some((midnight, day, offset)) = local_day(now + day_shift * DAY) else {
+                continue;
+            };
+            for point in &self.points {
+                if let Some(time) = self.point_time(point.time, midnight, day, offset) {
+                    timeline.push((time, point));
+                }
+            }
+        }
+        timeline.sort_by_key(|(time, _)| *time);
+        timeline
+    }
+
+    // Brightness and temperature as fractions for now, None for axes no point sets
+    pub fn values_at(&self, now: i64) -> (Option<f64>, Option<f64>) {
+        let timeline = self.timeline(now);
+        let transition = self.transition_minutes as i64 * 60;
+        let axis = |value: fn(&SchedulePoint) -> Option<u8>| {
+            let points: Vec<(i64, f64)> = timeline
+                .iter()
+                .filter_map(|(time, point)| Some((*time, value(point)? as f64 / 100.0)))
+                .collect();
+            let current = points.iter().rposition(|(time, _)| *time <= now)?;
+            let (time, target) = points[current];
+            let Some(&(_, previous)) = current.checked_sub(1).and_then(|i| points.get(i)) else {
+                return Some(target);
+            };
+            let progress = if transition > 0 {
+                ((now - time) as f64 / transition as f64).min(1.0)
+            } else {
+                1.0
+            };
+            Some(previous + (target - previous) * progress)
+        };
+        (axis(|p| p.brightness), axis(|p| p.temperature))
+    }
+
+    // Where a manual change stops holding
+    pub fn next_point(&self, now: i64) -> Option<i64> {
+        self.timeline(now)
+            .into_iter()
+            .map(|(time, _)| time)
+            .find(|time| *time > now)
+    }
+}
+
+pub struct FrontlightScheduler {
+    pub config: ScheduleConfig,
+    pub channel_rx: broadcast::Receiver<Requests>,
+    pub frontlight_tx: mpsc::Sender<BacklightControl>,
+    // Unix seconds, set by a manual change
+    pub override_until: Option<i64>,
+    // In percent, what the schedule set last
+    pub last_set: (Option<u8>, Option<u8>),
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+impl FrontlightScheduler {
+    pub async fn start(&mut self) {
+        if !self.config.enabled || self.config.points.is_empty() {
+            info!("Frontlight schedule is disabled");
+            return;
+        }
+        info!("Starting FrontlightScheduler");
+        loop {
+            self.apply().await;
+            tokio::select! {
+                _ = sleep(TICK) => {}
+                res = self.channel_rx.recv() => {
+                    if let Ok(Requests::SetBacklight(..) | Requests::SetFrontlight(..)) = res {
+                        self.manual_change();
+                    }
+                }
+            }
+        }
+    }
+
+    fn manual_change(&mut self) {
+        let now = unix_now();
+        self.override_until = self.config.next_point(now);
+        info!(
+            "Frontlight changed by hand, holding for {:?} minutes",
+            self.override_until.map(|until| (until - now) / 60)
+        );
+    }
+
+    async fn apply(&mut self) {
+        let now = unix_now();
+        if let Some(until) = self.override_until {
+            if now < until {
+                return;
+            }
+            debug!("Manual frontlight change is over");
+            self.override_until = None;
+            self.last_set = (None, None);
+        }
+
+        let percent = |value: Option<f64>| value.map(|v| (v * 100.0).round() as u8);
+        let (brightness, temperature) = self.config.values_at(now);
+        let (brightness, temperature) = (percent(brightness), percent(temperature));
+        let mut changes = Vec::new();
+        if let Some(value) = brightness.filter(|_| brightness != self.last_set.0) {
+            changes.push(FrontlightChange::Brightness(value));
+        }
+        if let Some(value) = temperature.filter(|_| temperature != self.last_set.1) {
+            changes.push(FrontlightChange::Temperature(value));
+        }
+        self.last_set = (brightness, temperature);
+        for change in changes {
+            debug!("Schedule sets {:?}", change);
+            if let Err(e) = self
+                .frontlight_tx
+                .send(BacklightControl::Frontlight(change))
+                .await
+            {
+                error!("Failed to send the frontlight change: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600;
+    // 2024-06-21 12:00 UTC, far from any daylight saving switch
+    const SUMMER_NOON: i64 = 1718971200;
+
+    // Local midnight of the test day, the schedule works in local time
+    fn midnight() -> i64 {
+        local_day(SUMMER_NOON).unwrap().0
+    }
+
+    fn at(hour: u8, minute: u8, brightness: Option<u8>, temperature: Option<u8>) -> SchedulePoint {
+        SchedulePoint {
+            time: ScheduleTime::At(hour, minute),
+            brightness,
+            temperature,
+        }
+    }
+
+    fn assert_minutes(minutes: f64, expected: f64) {
+        assert!(
+            (minutes - expected).abs() <= 2.0,
+            "{} != {}",
+            minutes,
+            expected
+        );
+    }
+
+    fn assert_fraction(value: Option<f64>, expected: f64) {
+        let value = value.unwrap();
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn sun_times_match_the_almanac() {
+        // London on the summer solstice, 03:43 and 20:21 UTC
+        let (sunrise, sunset) = sun_times(173, 51.5074, -0.1278).unwrap();
+        assert_minutes(sunrise, 3.0 * 60.0 + 43.0);
+        assert_minutes(sunset, 20.0 * 60.0 + 21.0);
+        // New York on the winter solstice, 12:16 and 21:32 UTC
+        let (sunrise, sunset) = sun_times(356, 40.7128, -74.0060).unwrap();
+        assert_minutes(sunrise, 12.0 * 60.0 + 16.0);
+        assert_minutes(sunset, 21.0 * 60.0 + 32.0);
+        // Sydney on new year's day rises the evening before in UTC, 18:47 and 09:09
+        let (sunrise, sunset) = sun_times(1, -33.8688, 151.2093).unwrap();
+        assert_minutes(sunrise, 18.0 * 60.0 + 47.0 - 24.0 * 60.0);
+        assert_minutes(sunset, 9.0 * 60.0 + 9.0);
+    }
+
+    #[test]
+    fn no_sun_times_at_polar_day_and_night() {
+        // Tromsø, the midnight sun and the polar night
+        assert_eq!(sun_times(173, 69.65, 18.96), None);
+        assert_eq!(sun_times(356, 69.65, 18.96), None);
+        // Both come back in spring
+        assert!(sun_times(80, 69.65, 18.96).is_some());
+    }
+
+    #[test]
+    fn sun_points_are_skipped_without_the_sun() {
+        let sun_point = |time| SchedulePoint {
+            time,
+            brightness: Some(10),
+            temperature: None,
+        };
+        let mut config = ScheduleConfig {
+            enabled: true,
+            latitude: Some(78.22),
+            longitude: Some(15.65),
+            transition_minutes: 0,
+            points: vec![
+                sun_point(ScheduleTime::Sunrise(0)),
+                sun_point(ScheduleTime::Sunset(-30)),
+                at(12, 0, Some(50), None),
+            ],
+        };
+        // Longyearbyen in June, only the noon point is left
+        let now = midnight() + 13 * HOUR;
+        assert_fraction(config.values_at(now).0, 0.5);
+        assert_eq!(
+            config.next_point(now),
+            Some(local_day(now + DAY).unwrap().0 + 12 * HOUR)
+        );
+
+        // Without coordinates there's no sun at all
+        config.latitude = None;
+        assert_fraction(config.values_at(now).0, 0.5);
+        assert_eq!(config.values_at(now).1, None);
+    }
+
+    #[test]
+    fn sun_points_follow_the_sun() {
+        let sun_point = |time, brightness| SchedulePoint {
+            time,
+            brightness: Some(brightness),
+            temperature: None,
+        };
+        let config = ScheduleConfig {
+            enabled: true,
+            latitude: Some(51.5074),
+            longitude: Some(-0.1278),
+            transition_minutes: 0,
+            points: vec![
+                sun_point(ScheduleTime::Sunset(-30), 80),
+                sun_point(ScheduleTime::Sunrise(30), 20),
+            ],
+        };
+        // London's sunset on the test day, wherever the local midnight is
+        let (_, sunset) = sun_times(173, 51.5074, -0.1278).unwrap();
+        let utc_midnight = SUMMER_NOON - 12 * HOUR;
+        let expected = utc_midnight + (sunset * 60.0) as i64 - 30 * 60;
+        // A neighbouring local day has it a few seconds off
+        let next = config.next_point(expected - 600).unwrap();
+        assert!((next - expected).abs() <= 120, "{} != {}", next, expected);
+        assert_fraction(config.values_at(next - 1).0, 0.2);
+        assert_fraction(config.values_at(next).0, 0.8);
+    }
+
+    #[test]
+    fn values_fade_in_over_the_transition() {
+        let config = ScheduleConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let midnight = midnight();
+        // 20% from 7:00, 70% from 19:00 fading in over 30 minutes
+        assert_fraction(config.values_at(midnight + 12 * HOUR).1, 0.2);
+        assert_fraction(config.values_at(midnight + 19 * HOUR).1, 0.2);
+        assert_fraction(config.values_at(midnight + 19 * HOUR + 15 * 60).1, 0.45);
+        assert_fraction(config.values_at(midnight + 19 * HOUR + 30 * 60).1, 0.7);
+        assert_fraction(config.values_at(midnight + 21 * HOUR).1, 0.7);
+        // The brightness only has the 22:00 point, yesterday's is the same
+        assert_fraction(config.values_at(midnight + 22 * HOUR + 6 * 60).0, 0.3);
+    }
+
+    #[test]
+    fn values_wrap_across_midnight() {
+        let config = ScheduleConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let tomorrow = local_day(midnight() + DAY).unwrap().0;
+        // Yesterday's 22:00 still holds in the early morning
+        assert_fraction(config.values_at(tomorrow + 2 * HOUR).0, 0.3);
+        assert_fraction(config.values_at(tomorrow + 2 * HOUR).1, 1.0);
+
+        // A transition running over midnight
+        let late = ScheduleConfig {
+            enabled: true,
+            transition_minutes: 30,
+            points: vec![at(12, 0, None, Some(0)), at(23, 50, None, Some(100))],
+            ..Default::default()
+        };
+        assert_fraction(late.values_at(tomorrow + 5 * 60).1, 0.5);
+        assert_fraction(late.values_at(tomorrow + 20 * 60).1, 1.0);
+        assert_eq!(late.values_at(tomorrow).0, None);
+    }
+
+    #[test]
+    fn next_point_is_the_next_in_time() {
+        let config = ScheduleConfig::default();
+        let midnight = midnight();
+        let tomorrow = local_day(midnight + DAY).unwrap().0;
+        assert_eq!(
+            config.next_point(midnight + 3 * HOUR),
+            Some(midnight + 7 * HOUR)
+        );
+        assert_eq!(
+            config.next_point(midnight + 8 * HOUR),
+            Some(midnight + 19 * HOUR)
+        );
+        // Strictly after, a point happening now is already in effect
+        assert_eq!(
+            config.next_point(midnight + 19 * HOUR),
+            Some(midnight + 22 * HOUR)
+        );
+        assert_eq!(
+            config.next_point(midnight + 23 * HOUR),
+            Some(tomorrow + 7 * HOUR)
+        );
+
+        // Points out of order in the config
+        let config = ScheduleConfig {
+            points: vec![
+                at(22, 0, Some(1), None),
+                at(6, 30, Some(2), None),
+                at(13, 0, Some(3), None),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.next_point(midnight + 7 * HOUR),
+            Some(midnight + 13 * HOUR)
+        );
+        assert_fraction(config.values_at(midnight + 7 * HOUR).0, 0.02);
+        assert_eq!(
+            ScheduleConfig {
+                points: Vec::new(),
+                ..config
+            }
+            .next_point(midnight),
+            None
+        );
+    }
+
+    fn scheduler() -> (FrontlightScheduler, mpsc::Receiver<BacklightControl>) {
+        let (frontlight_tx, frontlight_rx) = mpsc::channel(10);
+        let (_, channel_rx) = broadcast::channel(1);
+        let scheduler = FrontlightScheduler {
+            config: ScheduleConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            channel_rx,
+            frontlight_tx,
+            override_until: None,
+            last_set: (None, None),
+        };
+        (scheduler, frontlight_rx)
+    }
+
+    fn sent(frontlight_rx: &mut mpsc::Receiver<BacklightControl>) -> Vec<FrontlightChange> {
+        let mut changes = Vec::new();
+        while let Ok(control) = frontlight_rx.try_recv() {
+            match control {
+                BacklightControl::Frontlight(change) => changes.push(change),
+                BacklightControl::Limit(_) => panic!("the schedule doesn't limit"),
+            }
+        }
+        changes
+    }
+
+    #[tokio::test]
+    async fn manual_change_holds_until_the_next_point() {
+        let (mut scheduler, mut frontlight_rx) = scheduler();
+        scheduler.apply().await;
+        // The default points always set a temperature
+        assert!(!sent(&mut frontlight_rx).is_empty());
+        scheduler.apply().await;
+        assert_eq!(sent(&mut frontlight_rx), []);
+
+        let before = unix_now();
+        scheduler.manual_change();
+        let until = scheduler.override_until.unwrap();
+        assert!(until > before);
+        assert!(scheduler.config.next_point(before) <= Some(until));
+        assert!(until <= scheduler.config.next_point(unix_now()).unwrap());
+        // Nothing while it holds, not even what the schedule set before
+        scheduler.last_set = (None, None);
+        scheduler.apply().await;
+        assert_eq!(sent(&mut frontlight_rx), []);
+        assert_eq!(scheduler.override_until, Some(until));
+
+        // Once it's over, the schedule sets everything again
+        scheduler.override_until = Some(unix_now() - 1);
+        scheduler.last_set = (Some(1), Some(1));
+        scheduler.apply().await;
+        assert_eq!(scheduler.override_until, None);
+        let changes = sent(&mut frontlight_rx);
+        assert!(
+            changes
+                .iter()
+                .any(|change| matches!(change, FrontlightChange::Temperature(_)))
+        );
+    }
+}
@@ -1,118 +1,115 @@
-use std::time::Duration;
 use enums::Requests;
 use log::{debug, error, info};
-use tokio::{
-    process::{Child, Command},
-    time::sleep,
-};
+use serde::{Deserialize, Serialize};
 
+use crate::process::{ManagedProcess, ProcessHandle};
+
+// In tenths, 10 is a gamma of 1.0
 pub const DEFAULT_GAMMA: u8 = 10;
+// What gammastep accepts, 0.1 to 10.0
+const MIN_GAMMA: u8 = 1;
+const MAX_GAMMA: u8 = 100;
 
 pub enum GammaControl {
+    // Applied without changing the value set with requests, for presets and window settings
     Force(u8),
     PreviousValue,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GammaConfig {
+    // A driver parameter taking the gamma in tenths. When not set, gammastep is used
+    pub sysfs_path: Option<String>,
+}
+
 pub struct GammaListener {
     pub channel_rx: tokio::sync::broadcast::Receiver<Requests>,
     pub internal_channel_rx: tokio::sync::mpsc::Receiver<GammaControl>,
-    pub child: Option<Child>,
+    pub config: GammaConfig,
+    pub gammastep: Option<ProcessHandle>,
+    // Set with requests
     pub current_gamma: u8,
+    // On the screen now
+    pub applied_gamma: Option<u8>,
 }
 
 impl GammaListener {
     pub async fn start(&mut self) {
         info!("Starting GammaListener");
-
-        // Initial
-        self.toggle_gamma(10).await;
+        self.set_gamma(self.current_gamma).await;
 
         loop {
             tokio::select! {
                 res = self.channel_rx.recv() => {
-                    self.handle_channel_rx(res).await;
+                    if let Ok(Requests::Gamma(gamma)) = res {
+                        self.current_gamma = gamma.clamp(MIN_GAMMA, MAX_GAMMA);
+                        self.set_gamma(self.current_gamma).await;
+                    }
                 }
-                Some(gamma_value) = self.internal_channel_rx.recv() => {
-                    match gamma_value {
-                        GammaControl::Force(value) => {
-                            let previous = self.current_gamma;
-                            self.handle_internal_channel_rx(value).await;
-                            self.current_gamma = previous;
-                        },
-                        GammaControl::PreviousValue => {
-                            self.handle_internal_channel_rx(self.current_gamma).await
-                        },
+                Some(control) = self.internal_channel_rx.recv() => {
+                    match control {
+                        GammaControl::Force(gamma) => {
+                            self.set_gamma(gamma.clamp(MIN_GAMMA, MAX_GAMMA)).await
+                        }
+                        GammaControl::PreviousValue => self.set_gamma(self.current_gamma).await,
                     }
                 }
             }
         }
     }
 
-    pub async fn handle_internal_channel_rx(&mut self, gamma_value: u8) {
-        self.toggle_gamma(gamma_value).await;
+    async fn set_gamma(&mut self, gamma: u8) {
+        if self.applied_gamma == Some(gamma) && self.gamma_holds(gamma) {
+            return;
+        }
+        debug!("Setting gamma to: {:.1}", gamma as f32 / 10.0);
+        let result = match self.config.sysfs_path.clone() {
+            Some(path) => tokio::fs::write(&path, gamma.to_string())
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", path, e)),
+            None => {
+                self.run_gammastep(gamma).await;
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => self.applied_gamma = Some(gamma),
+            Err(e) => {
+                error!("{}", e);
+                self.applied_gamma = None;
+            }
+        }
     }
 
-    pub async fn handle_channel_rx(
-        &mut self,
-        res: Result<Requests, tokio::sync::broadcast::error::RecvError>,
-    ) {
-        if let Ok(data) = res {
-            match data {
-                Requests::GammaLevel => {
-                    let res = Command::new("eww")
-                        .arg("--no-daemonize")
-                        .arg("get")
-                        .arg("thresholding_level_value")
-                        .output()
-                        .await;
-                    if let Ok(value) = &res {
-                        let vec = &value.stdout;
-                        let str = String::from_utf8_lossy(&vec).to_string();
-                        if let Ok(gamma_level) = str.trim().parse() {
-                            self.toggle_gamma(gamma_level).await;
-                        } else {
-                            error!("Failed to parse gamma level: {:?}", res);
-                        }
-                    } else {
-                        error!("Failed to get thresholding_level_value");
-                    }
-                    sleep(Duration::from_millis(25)).await;
-                }
-                _ => {}
-            }
+    // A gammastep waiting to be restarted after a crash doesn't hold the gamma, a request
+    // starts it again right away
+    fn gamma_holds(&self, gamma: u8) -> bool {
+        if self.config.sysfs_path.is_some() || gamma == DEFAULT_GAMMA {
+            return true;
         }
+        self.gammastep
+            .as_ref()
+            .is_some_and(|gammastep| gammastep.pid().is_some())
     }
 
-    pub async fn toggle_gamma(&mut self, gamma_level: u8) {
-        // Not working yet ;/
-        return;
-        
-        if self.current_gamma == gamma_level {
-            return;
+    // The compositor drops the gamma ramp when its client exits, so gammastep has to keep
+    // running for as long as the gamma applies
+    async fn run_gammastep(&mut self, gamma: u8) {
+        if let Some(gammastep) = self.gammastep.take() {
+            gammastep.stop().await;
         }
-        if let Some(mut child) = self.child.take() {
-            if child.kill().await.is_err() {
-                Command::new("killall")
-                    .arg("-9")
-                    .arg("gammastep")
-                    .status()
-                    .await
-                    .ok();
-            };
+        if gamma == DEFAULT_GAMMA {
+            return;
         }
 
-        let f = gamma_level as f32 / 10.0;
-        debug!("Setting gamma level to: {}", f);
-        let child = Command::new("gammastep")
-            .arg("-l")
-            .arg("0.0:0.0")
-            .arg("-o")
-            .arg("-g")
-            .arg(format!("{:.1}:{:.1}:{:.1}", f, f, f))
-            .spawn()
-            .expect("failed to start gammastep");
-
-        self.child.replace(child);
-        self.current_gamma = gamma_level;
+        let f = gamma as f32 / 10.0;
+        let gamma_arg = format!("{:.1}:{:.1}:{:.1}", f, f, f);
+        self.gammastep = Some(
+            ManagedProcess::new("gammastep")
+                .args(["-m", "wayland", "-l", "0:0", "-t", "6500:6500", "-r"])
+                .args(["-g", &gamma_arg])
+                .spawn(),
+        );
     }
 }
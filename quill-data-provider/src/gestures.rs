@@ -1,33 +1,707 @@
-// No rotation support yet, so the only reason this exist is for rotation support
+// Touch gestures read straight from the touchscreen (multitouch protocol B). A gesture is
+// recognized once the last finger lifts, in the directions of the rotated screen
 
-use log::warn;
-use tokio::process::Command;
+use std::time::Duration;
 
-const COMMAND: &str = r#"
-lisgd -d /dev/input/by-path/platform-fe5e0000.i2c-event \
-      -w 1872 -h 1404 \
-      -g "2,LR,*,M,R,niri msg action focus-column-right" \
-      -g "2,RL,*,M,R,niri msg action focus-column-left"
-"#;
+use enums::Requests;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncReadExt,
+    process::Command,
+    sync::{broadcast, watch},
+    time::sleep,
+};
 
-#[derive(Default)]
+use crate::config::ProviderConfig;
+use crate::input::{
+    ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT, ABS_MT_TRACKING_ID, EV_ABS, EV_SYN,
+    INPUT_EVENT_SIZE, InputEvent, SYN_REPORT, abs_range, parse_events,
+};
+use crate::orientation::Orientation;
+use crate::pen::PenState;
+
+const DEFAULT_DEVICE: &str = "/dev/input/by-path/platform-fe5e0000.i2c-event";
+const MAX_SLOTS: usize = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PinchDirection {
+    In,
+    Out,
+}
+
+// The u8 is the number of fingers
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gesture {
+    Swipe(u8, Direction),
+    // One finger, starting at an edge
+    EdgeSwipe(Edge, Direction),
+    LongPress(u8),
+    Pinch(u8, PinchDirection),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GestureAction {
+    // Run with sh -c, like "niri msg action focus-column-right"
+    Command(String),
+    // Handled as if it came over the requests socket
+    Request(Requests),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GestureBinding {
+    pub gesture: Gesture,
+    pub action: GestureAction,
+}
+
+// Distances are fractions of the screen size
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureConfig {
+    pub enabled: bool,
+    pub device: String,
+    pub swipe_distance: f32,
+    pub edge_size: f32,
+    // How much fingers may move in a long press
+    pub move_tolerance: f32,
+    // How much the fingers need to spread or close for a pinch
+    pub pinch_distance: f32,
+    pub long_press_ms: u32,
+    pub bindings: Vec<GestureBinding>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            device: DEFAULT_DEVICE.to_string(),
+            swipe_distance: 0.15,
+            edge_size: 0.04,
+            move_tolerance: 0.02,
+            pinch_distance: 0.08,
+            long_press_ms: 700,
+            bindings: vec![
+                GestureBinding {
+                    gesture: Gesture::Swipe(2, Direction::Right),
+                    action: GestureAction::Command(
+                        "niri msg action focus-column-right".to_string(),
+                    ),
+                },
+                GestureBinding {
+                    gesture: Gesture::Swipe(2, Direction::Left),
+                    action: GestureAction::Command("niri msg action focus-column-left".to_string()),
+                },
+                GestureBinding {
+                    gesture: Gesture::Swipe(3, Direction::Down),
+                    action: GestureAction::Request(Requests::ScreenRefresh),
+                },
+                GestureBinding {
+                    gesture: Gesture::EdgeSwipe(Edge::Top, Direction::Down),
+                    action: GestureAction::Request(Requests::SettingsMenu),
+                },
+                GestureBinding {
+                    gesture: Gesture::EdgeSwipe(Edge::Bottom, Direction::Up),
+                    action: GestureAction::Request(Requests::VirtualKeyboard),
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Touch {
+    start: Option<(f32, f32)>,
+    current: (f32, f32),
+}
+
+impl Touch {
+    fn start(&self) -> (f32, f32) {
+        self.start.unwrap_or(self.current)
+    }
+
+    fn moved(&self) -> (f32, f32) {
+        let start = self.start();
+        (self.current.0 - start.0, self.current.1 - start.1)
+    }
+}
+
+// Fed one event at a time, so recorded sequences can be replayed
+pub struct Recognizer {
+    config: GestureConfig,
+    range_x: (i32, i32),
+    range_y: (i32, i32),
+    slot: usize,
+    // The last position of every slot, protocol B only sends what changed
+    positions: [(f32, f32); MAX_SLOTS],
+    touches: [Option<Touch>; MAX_SLOTS],
+    // Lifted during the current gesture
+    lifted: Vec<Touch>,
+    max_fingers: u8,
+    started: Option<Duration>,
+    orientation: Orientation,
+}
+
+impl Recognizer {
+    pub fn new(config: GestureConfig, range_x: (i32, i32), range_y: (i32, i32)) -> Self {
+        Self {
+            config,
+            range_x,
+            range_y,
+            slot: 0,
+            positions: [(0.0, 0.0); MAX_SLOTS],
+            touches: [None; MAX_SLOTS],
+            lifted: Vec::new(),
+            max_fingers: 0,
+            started: None,
+            orientation: Orientation::Normal,
+        }
+    }
+
+    // Takes effect with the next gesture
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    pub fn feed(&mut self, event: &InputEvent) -> Option<Gesture> {
+        match (event.event_type, event.code) {
+            (EV_ABS, ABS_MT_SLOT) => {
+                self.slot = (event.value.max(0) as usize).min(MAX_SLOTS - 1);
+            }
+            (EV_ABS, ABS_MT_TRACKING_ID) => {
+                if event.value < 0 {
+                    if let Some(mut touch) = self.touches[self.slot].take() {
+                        // A move in the same frame as the lift still counts
+                        touch.current = self.orientation.to_screen(self.positions[self.slot]);
+                        touch.start.get_or_insert(touch.current);
+                        self.lifted.push(touch);
+                    }
+                } else {
+                    self.started.get_or_insert(event.time);
+                    self.touches[self.slot] = Some(Touch {
+                        start: None,
+                        current: self.orientation.to_screen(self.positions[self.slot]),
+                    });
+                }
+            }
+            (EV_ABS, ABS_MT_POSITION_X) => {
+                self.positions[self.slot].0 = normalize(event.value, self.range_x);
+            }
+            (EV_ABS, ABS_MT_POSITION_Y) => {
+                self.positions[self.slot].1 = normalize(event.value, self.range_y);
+            }
+            (EV_SYN, SYN_REPORT) => return self.frame(event.time),
+            _ => {}
+        }
+        None
+    }
+
+    fn frame(&mut self, time: Duration) -> Option<Gesture> {
+        let mut fingers = 0;
+        for (slot, touch) in self.touches.iter_mut().enumerate() {
+            if let Some(touch) = touch {
+                touch.current = self.orientation.to_screen(self.positions[slot]);
+                touch.start.get_or_insert(touch.current);
+                fingers += 1;
+            }
+        }
+        self.max_fingers = self.max_fingers.max(fingers);
+        if fingers > 0 || self.lifted.is_empty() {
+            return None;
+        }
+
+        let duration = time.saturating_sub(self.started.take().unwrap_or(time));
+        let touches = std::mem::take(&mut self.lifted);
+        let fingers = std::mem::take(&mut self.max_fingers);
+        let gesture = self.recognize(&touches, fingers, duration);
+        debug!("{} finger gesture recognized as {:?}", fingers, gesture);
+        gesture
+    }
+
+    fn recognize(&self, touches: &[Touch], fingers: u8, duration: Duration) -> Option<Gesture> {
+        let count = touches.len() as f32;
+        let (dx, dy) = touches.iter().fold((0.0, 0.0), |(x, y), touch| {
+            let moved = touch.moved();
+            (x + moved.0 / count, y + moved.1 / count)
+        });
+
+        // Before swipes, spreading fingers barely move their center
+        if fingers >= 2 {
+            let spread_change = spread(touches, |t| t.current) - spread(touches, Touch::start);
+            if spread_change >= self.config.pinch_distance {
+                return Some(Gesture::Pinch(fingers, PinchDirection::Out));
+            }
+            if spread_change <= -self.config.pinch_distance {
+                return Some(Gesture::Pinch(fingers, PinchDirection::In));
+            }
+        }
+
+        if dx.abs().max(dy.abs()) >= self.config.swipe_distance {
+            let direction = match (dx.abs() > dy.abs(), dx > 0.0, dy > 0.0) {
+                (true, true, _) => Direction::Right,
+                (true, false, _) => Direction::Left,
+                (false, _, true) => Direction::Down,
+                (false, _, false) => Direction::Up,
+            };
+            if fingers == 1
+                && let Some(edge) = self.edge_at(touches[0].start())
+            {
+                return Some(Gesture::EdgeSwipe(edge, direction));
+            }
+            return Some(Gesture::Swipe(fingers, direction));
+        }
+
+        let still = touches.iter().all(|touch| {
+            let (x, y) = touch.moved();
+            x.abs().max(y.abs()) <= self.config.move_tolerance
+        });
+        if still && duration >= Duration::from_millis(self.config.long_press_ms as u64) {
+            return Some(Gesture::LongPress(fingers));
+        }
+        None
+    }
+
+    fn edge_at(&self, (x, y): (f32, f32)) -> Option<Edge> {
+        let edge_size = self.config.edge_size;
+        if x <= edge_size {
+            Some(Edge::Left)
+        } else if x >= 1.0 - edge_size {
+            Some(Edge::Right)
+        } else if y <= edge_size {
+            Some(Edge::Top)
+        } else if y >= 1.0 - edge_size {
+            Some(Edge::Bottom)
+        } else {
+            None
+        }
+    }
+}
+
+fn normalize(value: i32, (min, max): (i32, i32)) -> f32 {
+    if max <= min {
+        return 0.0;
+    }
+    (value - min) as f32 / (max - min) as f32
+}
+
+// Mean distance of the fingers from their center
+fn spread(touches: &[Touch], position: impl Fn(&Touch) -> (f32, f32)) -> f32 {
+    let count = touches.len() as f32;
+    let (cx, cy) = touches
+        .iter()
+        .map(&position)
+        .fold((0.0, 0.0), |(x, y), p| (x + p.0 / count, y + p.1 / count));
+    touches
+        .iter()
+        .map(|touch| {
+            let (x, y) = position(touch);
+            ((x - cx).powi(2) + (y - cy).powi(2)).sqrt() / count
+        })
+        .sum()
+}
+
+// Follows config reloads, reopening the device only when it changed
 pub struct GesturesManager {
-    // child: Option<tokio::process::Child>,
+    pub config_rx: watch::Receiver<ProviderConfig>,
+    pub orientation_rx: watch::Receiver<Orientation>,
+    pub request_tx: broadcast::Sender<Requests>,
+    pub pen_rx: watch::Receiver<PenState>,
 }
 
 impl GesturesManager {
     pub async fn start(&mut self) {
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(COMMAND)
-            .spawn()
-            .expect("Failed to spawn command");
-        // self.child = Some(child);
-        if let Ok(status) = child.wait().await {
-            if !status.success() {
-                warn!("Gestures exited and failed?");
+        info!("Starting GesturesManager");
+        loop {
+            let config = self.config_rx.borrow_and_update().gestures.clone();
+            if !config.enabled {
+                info!("Gestures are disabled");
+                if self.config_rx.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            // The touchscreen can come and go, like over a suspend
+            if let Err(e) = self.watch_device(config.clone()).await {
+                warn!("Gestures device {}: {}", config.device, e);
+                sleep(Duration::from_secs(5)).await;
             }
         }
     }
+
+    // Returns Ok when the config needs the device reopened
+    async fn watch_device(&mut self, mut config: GestureConfig) -> std::io::Result<()> {
+        // Opening and the ioctls block
+        let device = config.device.clone();
+        let (file, range_x, range_y) = tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(&device)?;
+            let range_x = abs_range(&file, ABS_MT_POSITION_X)?;
+            let range_y = abs_range(&file, ABS_MT_POSITION_Y)?;
+            Ok::<_, std::io::Error>((file, range_x, range_y))
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        debug!("Touchscreen ranges: x {:?}, y {:?}", range_x, range_y);
+        let mut recognizer = Recognizer::new(config.clone(), range_x, range_y);
+
+        let mut file = tokio::fs::File::from_std(file);
+        let mut buf = [0u8; INPUT_EVENT_SIZE * 64];
+        loop {
+            tokio::select! {
+                read = file.read(&mut buf) => {
+                    let read = read?;
+                    if read == 0 {
+                        return Err(std::io::ErrorKind::UnexpectedEof.into());
+                    }
+                    recognizer.set_orientation(*self.orientation_rx.borrow());
+                    for event in parse_events(&buf[..read]) {
+                        if let Some(gesture) = recognizer.feed(&event) {
+                            self.run(&config, gesture);
+                        }
+                    }
+                }
+                res = self.config_rx.changed() => {
+                    if res.is_err() {
+                        return Ok(());
+                    }
+                    let new_config = self.config_rx.borrow_and_update().gestures.clone();
+                    if new_config.device != config.device || !new_config.enabled {
+                        return Ok(());
+                    }
+                    debug!("Gestures config reloaded");
+                    recognizer.set_config(new_config.clone());
+                    config = new_config;
+                }
+            }
+        }
+    }
+
+    fn run(&self, config: &GestureConfig, gesture: Gesture) {
+        let Some(binding) = config.bindings.iter().find(|b| b.gesture == gesture) else {
+            return;
+        };
+        if self.config_rx.borrow().pen.palm_rejection && self.pen_rx.borrow().in_proximity {
+            debug!("Ignoring {:?}, the pen is in proximity", gesture);
+            return;
+        }
+        info!("Running {:?} for {:?}", binding.action, gesture);
+        match &binding.action {
+            GestureAction::Command(command) => {
+                tokio::spawn(run_command(command.clone()));
+            }
+            GestureAction::Request(request) => {
+                if let Err(e) = self.request_tx.send(request.clone()) {
+                    error!("Failed to send {:?}: {}", request, e);
+                }
+            }
+        }
+    }
+}
+
+// Awaited in its own task, so a command which keeps running doesn't hold up the gestures
+async fn run_command(command: String) {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .output()
+        .await;
+    match output {
+        Ok(output) if !output.status.success() => warn!(
+            "{} exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(output) if !output.stderr.is_empty() => debug!(
+            "{}: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(_) => {}
+        Err(e) => error!("Failed to run {}: {}", command, e),
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The PineNote's touchscreen
+    const RANGE_X: (i32, i32) = (0, 1403);
+    const RANGE_Y: (i32, i32) = (0, 1871);
+
+    const ABS: u16 = EV_ABS;
+    const SYN: u16 = EV_SYN;
+    const SLOT: u16 = ABS_MT_SLOT;
+    const ID: u16 = ABS_MT_TRACKING_ID;
+    const X: u16 = ABS_MT_POSITION_X;
+    const Y: u16 = ABS_MT_POSITION_Y;
+
+    // Like evtest shows them: milliseconds, type, code, value
+    type Recording = [(u64, u16, u16, i32)];
+
+    // As the kernel writes them, so the parsing is replayed too
+    fn replay(recognizer: &mut Recognizer, recording: &Recording) -> Vec<Gesture> {
+        let bytes: Vec<u8> = recording
+            .iter()
+            .flat_map(|&(ms, event_type, code, value)| {
+                let mut event = Vec::with_capacity(INPUT_EVENT_SIZE);
+                event.extend(((ms / 1000) as i64).to_ne_bytes());
+                event.extend((((ms % 1000) * 1000) as i64).to_ne_bytes());
+                event.extend(event_type.to_ne_bytes());
+                event.extend(code.to_ne_bytes());
+                event.extend(value.to_ne_bytes());
+                event
+            })
+            .collect();
+        parse_events(&bytes)
+            .filter_map(|event| recognizer.feed(&event))
+            .collect()
+    }
+
+    fn recognize(orientation: Orientation, recording: &Recording) -> Vec<Gesture> {
+        let mut recognizer = Recognizer::new(GestureConfig::default(), RANGE_X, RANGE_Y);
+        recognizer.set_orientation(orientation);
+        replay(&mut recognizer, recording)
+    }
+
+    const TWO_FINGERS_DOWN: &Recording = &[
+        (0, ABS, SLOT, 0),
+        (0, ABS, ID, 41),
+        (0, ABS, X, 500),
+        (0, ABS, Y, 600),
+        (0, ABS, SLOT, 1),
+        (0, ABS, ID, 42),
+        (0, ABS, X, 800),
+        (0, ABS, Y, 610),
+        (0, SYN, SYN_REPORT, 0),
+        (40, ABS, SLOT, 0),
+        (40, ABS, Y, 900),
+        (40, ABS, SLOT, 1),
+        (40, ABS, Y, 905),
+        (40, SYN, SYN_REPORT, 0),
+        (80, ABS, SLOT, 0),
+        (80, ABS, Y, 1200),
+        (80, ABS, SLOT, 1),
+        (80, ABS, Y, 1210),
+        (80, SYN, SYN_REPORT, 0),
+        (95, ABS, SLOT, 0),
+        (95, ABS, ID, -1),
+        (95, SYN, SYN_REPORT, 0),
+        (110, ABS, SLOT, 1),
+        (110, ABS, ID, -1),
+        (110, SYN, SYN_REPORT, 0),
+    ];
+
+    #[test]
+    fn two_finger_swipes() {
+        assert_eq!(
+            recognize(Orientation::Normal, TWO_FINGERS_DOWN),
+            [Gesture::Swipe(2, Direction::Down)]
+        );
+        let right: &Recording = &[
+            (0, ABS, SLOT, 0),
+            (0, ABS, ID, 7),
+            (0, ABS, X, 300),
+            (0, ABS, Y, 900),
+            (0, ABS, SLOT, 1),
+            (0, ABS, ID, 8),
+            (0, ABS, X, 310),
+            (0, ABS, Y, 1100),
+            (0, SYN, SYN_REPORT, 0),
+            (50, ABS, SLOT, 0),
+            (50, ABS, X, 500),
+            (50, ABS, SLOT, 1),
+            (50, ABS, X, 520),
+            (50, SYN, SYN_REPORT, 0),
+            (100, ABS, SLOT, 0),
+            (100, ABS, X, 700),
+            (100, ABS, ID, -1),
+            (100, ABS, SLOT, 1),
+            (100, ABS, X, 705),
+            (100, ABS, ID, -1),
+            (100, SYN, SYN_REPORT, 0),
+        ];
+        assert_eq!(
+            recognize(Orientation::Normal, right),
+            [Gesture::Swipe(2, Direction::Right)]
+        );
+    }
+
+    #[test]
+    fn three_finger_swipe() {
+        let up: &Recording = &[
+            (0, ABS, SLOT, 0),
+            (0, ABS, ID, 1),
+            (0, ABS, X, 400),
+            (0, ABS, Y, 1400),
+            (0, ABS, SLOT, 1),
+            (0, ABS, ID, 2),
+            (0, ABS, X, 700),
+            (0, ABS, Y, 1380),
+            (0, SYN, SYN_REPORT, 0),
+            // The third finger lands a bit later
+            (20, ABS, SLOT, 2),
+            (20, ABS, ID, 3),
+            (20, ABS, X, 1000),
+            (20, ABS, Y, 1410),
+            (20, SYN, SYN_REPORT, 0),
+            (70, ABS, SLOT, 0),
+            (70, ABS, Y, 900),
+            (70, ABS, SLOT, 1),
+            (70, ABS, Y, 880),
+            (70, ABS, SLOT, 2),
+            (70, ABS, Y, 910),
+            (70, SYN, SYN_REPORT, 0),
+            (90, ABS, SLOT, 0),
+            (90, ABS, ID, -1),
+            (90, ABS, SLOT, 1),
+            (90, ABS, ID, -1),
+            (90, ABS, SLOT, 2),
+            (90, ABS, ID, -1),
+            (90, SYN, SYN_REPORT, 0),
+        ];
+        assert_eq!(
+            recognize(Orientation::Normal, up),
+            [Gesture::Swipe(3, Direction::Up)]
+        );
+    }
+
+    // One finger from y, to y + distance, in the middle of the width
+    fn one_finger(y: i32, distance: i32, lift_ms: u64) -> Vec<(u64, u16, u16, i32)> {
+        vec![
+            (0, ABS, SLOT, 0),
+            (0, ABS, ID, 90),
+            (0, ABS, X, 700),
+            (0, ABS, Y, y),
+            (0, SYN, SYN_REPORT, 0),
+            (lift_ms / 2, ABS, Y, y + distance / 2),
+            (lift_ms / 2, SYN, SYN_REPORT, 0),
+            (lift_ms, ABS, Y, y + distance),
+            (lift_ms, ABS, ID, -1),
+            (lift_ms, SYN, SYN_REPORT, 0),
+        ]
+    }
+
+    #[test]
+    fn edge_swipes() {
+        assert_eq!(
+            recognize(Orientation::Normal, &one_finger(20, 600, 150)),
+            [Gesture::EdgeSwipe(Edge::Top, Direction::Down)]
+        );
+        assert_eq!(
+            recognize(Orientation::Normal, &one_finger(1860, -500, 150)),
+            [Gesture::EdgeSwipe(Edge::Bottom, Direction::Up)]
+        );
+        // Away from the edges it's a plain swipe
+        assert_eq!(
+            recognize(Orientation::Normal, &one_finger(600, 600, 150)),
+            [Gesture::Swipe(1, Direction::Down)]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        assert_eq!(
+            recognize(Orientation::Normal, &one_finger(900, 12, 900)),
+            [Gesture::LongPress(1)]
+        );
+        // Too short, a tap is left to the compositor
+        assert!(recognize(Orientation::Normal, &one_finger(900, 12, 300)).is_empty());
+        // Moved too much for a press, not enough for a swipe
+        assert!(recognize(Orientation::Normal, &one_finger(900, 150, 900)).is_empty());
+    }
+
+    // Two fingers on a horizontal line, from x1 and x2 to x1 + spread and x2 - spread
+    fn pinch(x1: i32, x2: i32, spread: i32) -> Vec<(u64, u16, u16, i32)> {
+        vec![
+            (0, ABS, SLOT, 0),
+            (0, ABS, ID, 11),
+            (0, ABS, X, x1),
+            (0, ABS, Y, 900),
+            (0, ABS, SLOT, 1),
+            (0, ABS, ID, 12),
+            (0, ABS, X, x2),
+            (0, ABS, Y, 900),
+            (0, SYN, SYN_REPORT, 0),
+            (60, ABS, SLOT, 0),
+            (60, ABS, X, x1 + spread),
+            (60, ABS, SLOT, 1),
+            (60, ABS, X, x2 - spread),
+            (60, SYN, SYN_REPORT, 0),
+            (120, ABS, SLOT, 0),
+            (120, ABS, ID, -1),
+            (120, ABS, SLOT, 1),
+            (120, ABS, ID, -1),
+            (120, SYN, SYN_REPORT, 0),
+        ]
+    }
+
+    #[test]
+    fn pinches() {
+        assert_eq!(
+            recognize(Orientation::Normal, &pinch(600, 800, -300)),
+            [Gesture::Pinch(2, PinchDirection::Out)]
+        );
+        assert_eq!(
+            recognize(Orientation::Normal, &pinch(300, 1100, 300)),
+            [Gesture::Pinch(2, PinchDirection::In)]
+        );
+    }
+
+    #[test]
+    fn gestures_follow_the_rotation() {
+        // Down the panel is to the left of a screen turned by 90 degrees
+        assert_eq!(
+            recognize(Orientation::Rotate90, TWO_FINGERS_DOWN),
+            [Gesture::Swipe(2, Direction::Left)]
+        );
+        assert_eq!(
+            recognize(Orientation::Rotate180, TWO_FINGERS_DOWN),
+            [Gesture::Swipe(2, Direction::Up)]
+        );
+        // And the top edge of the panel is its right edge
+        assert_eq!(
+            recognize(Orientation::Rotate90, &one_finger(20, 600, 150)),
+            [Gesture::EdgeSwipe(Edge::Right, Direction::Left)]
+        );
+    }
+
+    #[test]
+    fn gestures_in_a_row() {
+        let mut recognizer = Recognizer::new(GestureConfig::default(), RANGE_X, RANGE_Y);
+        let mut recording = TWO_FINGERS_DOWN.to_vec();
+        recording.extend(
+            pinch(600, 800, -300)
+                .into_iter()
+                .map(|(ms, event_type, code, value)| (ms + 1000, event_type, code, value)),
+        );
+        assert_eq!(
+            replay(&mut recognizer, &recording),
+            [
+                Gesture::Swipe(2, Direction::Down),
+                Gesture::Pinch(2, PinchDirection::Out)
+            ]
+        );
+    }
+}
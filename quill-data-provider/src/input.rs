@@ -0,0 +1,169 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use tokio::{fs::File, io::AsyncReadExt, task::JoinSet};
+
+const INPUT_DIR: &str = "/dev/input";
+// struct input_event on 64 bit: timeval (16) + type (2) + code (2) + value (4)
+pub const INPUT_EVENT_SIZE: usize = 24;
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+pub const SYN_REPORT: u16 = 0x00;
+pub const BTN_TOOL_PEN: u16 = 0x140;
+pub const BTN_TOOL_RUBBER: u16 = 0x141;
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const BTN_STYLUS: u16 = 0x14b;
+pub const BTN_STYLUS2: u16 = 0x14c;
+const KEY_MAX: usize = 0x2ff;
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InputEvent {
+    // Since the epoch, from the kernel
+    pub time: Duration,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl InputEvent {
+    pub fn from_bytes(bytes: &[u8; INPUT_EVENT_SIZE]) -> Self {
+        let i64_at = |i: usize| i64::from_ne_bytes(bytes[i..i + 8].try_into().unwrap());
+        let seconds = i64_at(0).max(0) as u64;
+        let micros = i64_at(8).clamp(0, 999_999) as u32;
+        InputEvent {
+            time: Duration::new(seconds, micros * 1000),
+            event_type: u16::from_ne_bytes([bytes[16], bytes[17]]),
+            code: u16::from_ne_bytes([bytes[18], bytes[19]]),
+            value: i32::from_ne_bytes(bytes[20..24].try_into().unwrap()),
+        }
+    }
+}
+
+// Reads are always whole events, but a short one is skipped instead of trusted
+pub fn parse_events(buf: &[u8]) -> impl Iterator<Item = InputEvent> + '_ {
+    buf.chunks_exact(INPUT_EVENT_SIZE)
+        .map(|chunk| InputEvent::from_bytes(chunk.try_into().unwrap()))
+}
+
+// struct input_absinfo: value, minimum, maximum, fuzz, flat, resolution
+const ABSINFO_SIZE: usize = 24;
+
+// The range of an absolute axis, with the EVIOCGABS ioctl
+pub fn abs_range(file: &std::fs::File, axis: u16) -> std::io::Result<(i32, i32)> {
+    use std::os::fd::AsRawFd;
+    // _IOR('E', 0x40 + axis, struct input_absinfo)
+    let request = (2u64 << 30) | ((ABSINFO_SIZE as u64) << 16) | (0x45 << 8) | (0x40 + axis as u64);
+    let mut info = [0i32; ABSINFO_SIZE / 4];
+    // Safety: info is as big as what the ioctl writes
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, info.as_mut_ptr()) };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((info[1], info[2]))
+}
+
+// If the device can send a key, with the EVIOCGBIT ioctl for EV_KEY
+pub fn has_key(file: &std::fs::File, key: u16) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+    let mut bits = [0u8; KEY_MAX / 8 + 1];
+    // _IOC(_IOC_READ, 'E', 0x20 + EV_KEY, len)
+    let request = (2u64 << 30) | ((bits.len() as u64) << 16) | (0x45 << 8) | (0x20 + EV_KEY as u64);
+    // Safety: bits is as big as the length in the request
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, bits.as_mut_ptr()) };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let key = key as usize;
+    Ok(bits
+        .get(key / 8)
+        .is_some_and(|byte| byte & (1 << (key % 8)) != 0))
+}
+
+// The first event device which can send a key, like BTN_TOOL_PEN for the stylus
+pub fn find_device_with_key(key: u16) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(INPUT_DIR)
+        .inspect_err(|e| error!("Failed to read {}: {}", INPUT_DIR, e))
+        .ok()?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+        })
+        .collect();
+    paths.sort();
+    paths.into_iter().find(|path| {
+        std::fs::File::open(path)
+            .and_then(|file| has_key(&file, key))
+            .unwrap_or(false)
+    })
+}
+
+// Only tracks when the last input happened, for anything that waits on the user being idle.
+// Devices are scanned once at startup
+pub struct InputActivityListener {
+    pub activity_tx: tokio::sync::watch::Sender<Instant>,
+}
+
+impl InputActivityListener {
+    pub async fn start(&mut self) {
+        info!("Starting InputActivityListener");
+        let mut entries = match tokio::fs::read_dir(INPUT_DIR).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to read {}: {}", INPUT_DIR, e);
+                return;
+            }
+        };
+
+        let mut devices = JoinSet::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let is_event_device = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("event"));
+            if is_event_device {
+                devices.spawn(watch_device(path, self.activity_tx.clone()));
+            }
+        }
+
+        while devices.join_next().await.is_some() {}
+        warn!("No input devices left to watch");
+    }
+}
+
+async fn watch_device(path: PathBuf, activity_tx: tokio::sync::watch::Sender<Instant>) {
+    let mut file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to open input device {:?}: {}", path, e);
+            return;
+        }
+    };
+    debug!("Watching input device {:?}", path);
+
+    let mut buf = [0u8; INPUT_EVENT_SIZE * 64];
+    loop {
+        match file.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {
+                activity_tx.send_replace(Instant::now());
+            }
+            Err(e) => {
+                warn!("Failed to read input device {:?}: {}", path, e);
+                break;
+            }
+        }
+    }
+}
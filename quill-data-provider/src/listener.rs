@@ -39,4 +39,22 @@ pub trait SocketHandler {
             self.send_unix(unix, str).await;
         }
     }
+
+    // For topics which only publish a state kept somewhere else
+    async fn forward_watch(
+        &self,
+        unix: &mut tokio::net::UnixStream,
+        rx: &mut tokio::sync::watch::Receiver<String>,
+    ) {
+        loop {
+            let value = rx.borrow_and_update().clone();
+            if !value.is_empty() {
+                self.send_unix(unix, value).await;
+            }
+            if rx.changed().await.is_err() {
+                error!("Sender for {} dropped", Self::SOCKET_NAME);
+                break;
+            }
+        }
+    }
 }
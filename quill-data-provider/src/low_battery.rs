@@ -0,0 +1,260 @@
+use enums::Requests;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    process::Command,
+    sync::{broadcast, mpsc, watch},
+};
+
+use crate::backlight::BacklightControl;
+use crate::battery::BatteryInfo;
+
+// A threshold fires again only once the battery got this much above it, or charged
+const HYSTERESIS: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LowBatteryAction {
+    // Shown by the notification daemon, urgent below the last threshold
+    Notify(String),
+    // Preset name, like a slower one which refreshes less
+    ApplyPreset(String),
+    // Percent of the maximum the frontlight is lowered to, the schedule and auto brightness
+    // stay under it until the battery charges
+    DimBacklight(u8),
+    Suspend,
+    PowerOff,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LowBatteryThreshold {
+    // In percent, fires once the capacity is at or below it while discharging
+    pub capacity: u8,
+    pub actions: Vec<LowBatteryAction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LowBatteryConfig {
+    pub enabled: bool,
+    pub thresholds: Vec<LowBatteryThreshold>,
+}
+
+impl Default for LowBatteryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            thresholds: vec![
+                LowBatteryThreshold {
+                    capacity: 15,
+                    actions: vec![LowBatteryAction::Notify("Battery low".to_string())],
+                },
+                LowBatteryThreshold {
+                    capacity: 7,
+                    actions: vec![
+                        LowBatteryAction::Notify("Battery very low, dimming the light".to_string()),
+                        LowBatteryAction::DimBacklight(20),
+                    ],
+                },
+                LowBatteryThreshold {
+                    capacity: 3,
+                    actions: vec![
+                        LowBatteryAction::Notify("Battery critical, suspending".to_string()),
+                        LowBatteryAction::Suspend,
+                    ],
+                },
+            ],
+        }
+    }
+}
+
+// Which thresholds fired, so each one only runs once per discharge
+#[derive(Default)]
+pub struct LowBatteryMonitor {
+    fired: Vec<bool>,
+}
+
+impl LowBatteryMonitor {
+    // The indexes of the thresholds crossed since the last update
+    pub fn update(&mut self, config: &LowBatteryConfig, info: &BatteryInfo) -> Vec<usize> {
+        self.fired.resize(config.thresholds.len(), false);
+        let Some(capacity) = info.capacity else {
+            return Vec::new();
+        };
+        let discharging =
+            info.status.as_deref() == Some("Discharging") && info.charger_online != Some(true);
+
+        let mut crossed = Vec::new();
+        for (i, threshold) in config.thresholds.iter().enumerate() {
+            if !discharging || capacity >= threshold.capacity.saturating_add(HYSTERESIS) {
+                self.fired[i] = false;
+            } else if capacity <= threshold.capacity && !self.fired[i] {
+                self.fired[i] = true;
+                crossed.push(i);
+            }
+        }
+        crossed
+    }
+
+    // The lowest dim of the thresholds which fired and weren't reset yet
+    pub fn limit(&self, config: &LowBatteryConfig) -> Option<u8> {
+        config
+            .thresholds
+            .iter()
+            .zip(&self.fired)
+            .filter(|(_, fired)| **fired)
+            .flat_map(|(threshold, _)| &threshold.actions)
+            .filter_map(|action| match action {
+                LowBatteryAction::DimBacklight(percent) => Some(*percent),
+                _ => None,
+            })
+            .min()
+    }
+}
+
+pub struct LowBatteryListener {
+    pub config: LowBatteryConfig,
+    pub channel_tx: broadcast::Sender<Requests>,
+    pub frontlight_tx: mpsc::Sender<BacklightControl>,
+    pub battery_rx: watch::Receiver<BatteryInfo>,
+}
+
+impl LowBatteryListener {
+    pub async fn start(&mut self) {
+        if !self.config.enabled || self.config.thresholds.is_empty() {
+            info!("Low battery actions are disabled");
+            return;
+        }
+        info!("Starting LowBatteryListener");
+        let mut monitor = LowBatteryMonitor::default();
+        let mut limit = None;
+        loop {
+            let info = self.battery_rx.borrow_and_update().clone();
+            // Only the lowest one when several are crossed at once, like after a resume
+            if let Some(&index) = monitor
+                .update(&self.config, &info)
+                .iter()
+                .min_by_key(|&&i| self.config.thresholds[i].capacity)
+            {
+                self.run(index).await;
+            }
+            if monitor.limit(&self.config) != limit {
+                limit = monitor.limit(&self.config);
+                if let Err(e) = self
+                    .frontlight_tx
+                    .send(BacklightControl::Limit(limit))
+                    .await
+                {
+                    error!("Failed to send the frontlight limit: {}", e);
+                }
+            }
+            if self.battery_rx.changed().await.is_err() {
+                error!("Battery sender dropped");
+                break;
+            }
+        }
+    }
+
+    async fn run(&self, index: usize) {
+        let threshold = &self.config.thresholds[index];
+        warn!("Battery at or below {}%", threshold.capacity);
+        let critical = self
+            .config
+            .thresholds
+            .iter()
+            .all(|t| t.capacity >= threshold.capacity);
+        for action in &threshold.actions {
+            info!("Low battery action: {:?}", action);
+            match action {
+                LowBatteryAction::Notify(message) => notify(message, critical).await,
+                LowBatteryAction::ApplyPreset(name) => {
+                    if let Err(e) = self.channel_tx.send(Requests::ApplyPreset(name.clone())) {
+                        error!("Failed to request preset {}: {}", name, e);
+                    }
+                }
+                // Sent as a limit once the thresholds are updated
+                LowBatteryAction::DimBacklight(_) => {}
+                LowBatteryAction::Suspend => systemctl("suspend").await,
+                LowBatteryAction::PowerOff => systemctl("poweroff").await,
+            }
+        }
+    }
+}
+
+async fn notify(message: &str, critical: bool) {
+    let urgency = if critical { "critical" } else { "normal" };
+    let result = Command::new("notify-send")
+        .args(["--app-name", "Battery", "--urgency", urgency, message])
+        .status()
+        .await;
+    if let Err(e) = result {
+        error!("Failed to run notify-send: {}", e);
+    }
+}
+
+async fn systemctl(action: &str) {
+    match Command::new("systemctl").arg(action).status().await {
+        Ok(status) if !status.success() => error!("systemctl {} exited with {}", action, status),
+        Ok(_) => {}
+        Err(e) => error!("Failed to run systemctl {}: {}", action, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn battery(capacity: u8, status: &str) -> BatteryInfo {
+        BatteryInfo {
+            capacity: Some(capacity),
+            status: Some(status.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn limit_holds_until_charging() {
+        let config = LowBatteryConfig::default();
+        let mut monitor = LowBatteryMonitor::default();
+        assert!(
+            monitor
+                .update(&config, &battery(20, "Discharging"))
+                .is_empty()
+        );
+        assert_eq!(monitor.limit(&config), None);
+        assert_eq!(monitor.update(&config, &battery(14, "Discharging")), [0]);
+        assert_eq!(monitor.limit(&config), None);
+        assert_eq!(monitor.update(&config, &battery(7, "Discharging")), [1]);
+        assert_eq!(monitor.limit(&config), Some(20));
+        // Held while the schedule or auto brightness would raise the light again
+        assert!(
+            monitor
+                .update(&config, &battery(6, "Discharging"))
+                .is_empty()
+        );
+        assert_eq!(monitor.limit(&config), Some(20));
+        assert!(monitor.update(&config, &battery(6, "Charging")).is_empty());
+        assert_eq!(monitor.limit(&config), None);
+    }
+
+    #[test]
+    fn lowest_dim_wins() {
+        let mut config = LowBatteryConfig::default();
+        config.thresholds[2]
+            .actions
+            .push(LowBatteryAction::DimBacklight(5));
+        let mut monitor = LowBatteryMonitor::default();
+        // Both cross at once, like after a resume
+        assert_eq!(
+            monitor.update(&config, &battery(2, "Discharging")),
+            [0, 1, 2]
+        );
+        assert_eq!(monitor.limit(&config), Some(5));
+        // Above the last one with its hysteresis
+        assert!(
+            monitor
+                .update(&config, &battery(5, "Discharging"))
+                .is_empty()
+        );
+        assert_eq!(monitor.limit(&config), Some(20));
+    }
+}
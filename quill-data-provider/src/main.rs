@@ -1,23 +1,43 @@
+pub mod auto_brightness;
 pub mod backlight;
 pub mod battery;
+pub mod battery_history;
 pub mod bluetooth;
+pub mod config;
 pub mod dunst;
 pub mod eink;
 pub mod eink_listener;
-// pub mod gamma;
+pub mod focus;
+pub mod frontlight_schedule;
+pub mod gamma;
 pub mod gestures;
+pub mod input;
 pub mod listener;
+pub mod low_battery;
 pub mod network;
+pub mod orientation;
+pub mod pen;
 pub mod player;
+pub mod process;
+pub mod refresh;
 pub mod requests;
 pub mod settingsmenu;
+pub mod uevent;
 pub mod virtualkeyboard;
 pub mod volume;
 
-use backlight::CoolBacklightListener;
-use backlight::WarmBacklightListener;
-use battery::{BatteryPercentListener, BatteryStateListener};
+use auto_brightness::AutoBrightness;
+use backlight::{
+    BacklightController, ChannelBrightness, CoolBacklightListener, FrontlightListener,
+    WarmBacklightListener,
+};
+use battery::{
+    BatteryListener, BatteryPercentListener, BatteryStateListener, POWER_SUPPLY_DIR,
+    PowerSupplyMonitor,
+};
+use battery_history::{BatteryHistoryListener, BatteryHistoryTopicListener, HistoryFile};
 use bluetooth::BluetoothListener;
+use config::{ConfigListener, ProviderConfig, SlidersListener, config_path};
 use enums::Requests;
 use listener::SocketHandler;
 use log::*;
@@ -27,9 +47,18 @@ use tokio::sync::broadcast;
 use volume::VolumeListener;
 
 use crate::dunst::DunstListener;
-use crate::eink_listener::EinkListener;
+use crate::eink_listener::{EinkListener, EinkPresetListener};
+use crate::focus::FocusListener;
+use crate::frontlight_schedule::FrontlightScheduler;
+use crate::gamma::{DEFAULT_GAMMA, GammaListener};
 use crate::gestures::GesturesManager;
+use crate::input::InputActivityListener;
+use crate::low_battery::LowBatteryListener;
+use crate::orientation::{OrientationListener, OrientationTopicListener};
+use crate::pen::{PenListener, PenTopicListener};
+use crate::refresh::{RefreshManager, RefreshStatsListener};
 use crate::settingsmenu::SettingsMenuListener;
+use crate::uevent::{UeventListener, UeventSubscription};
 use crate::virtualkeyboard::VirtualKeyboardListener;
 
 #[tokio::main]
@@ -37,6 +66,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("none")).init();
     debug!("Starting eww-data-provider");
 
+    let config = ProviderConfig::load(&config_path());
+    debug!("Config: {:#?}", config);
+    let (config_tx, config_rx) = tokio::sync::watch::channel(config.clone());
+
     let (tx, _rx) = broadcast::channel::<Requests>(16);
     let request_tx = tx.clone();
     tokio::spawn(async move {
@@ -47,6 +80,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    let (uevent_tx, _uevent_rx) = broadcast::channel(64);
+    let mut uevent_listener = UeventListener {
+        uevent_tx: uevent_tx.clone(),
+    };
+    tokio::spawn(async move {
+        uevent_listener.start().await;
+    });
+
     let mut dunst = DunstListener {
         channel: tx.subscribe(),
     };
@@ -62,28 +103,106 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         vkeyboard.start().await;
     });
 
-    /*
     let (gamma_channel_tx, gamma_channel_rx) = tokio::sync::mpsc::channel(10);
     let mut gamma = GammaListener {
         channel_rx: tx.subscribe(),
-        child: None,
         internal_channel_rx: gamma_channel_rx,
-        current_gamma: 0,
+        config: config.gamma.clone(),
+        gammastep: None,
+        current_gamma: DEFAULT_GAMMA,
+        applied_gamma: None,
     };
     tokio::spawn(async move {
         gamma.start().await;
     });
-    */
 
+    let (focus_tx, focus_rx) = tokio::sync::watch::channel(Default::default());
+    let mut focus_listener = FocusListener {
+        focus_tx,
+        windows: Default::default(),
+    };
+    tokio::spawn(async move {
+        focus_listener.start().await;
+    });
+
+    let (activity_tx, activity_rx) = tokio::sync::watch::channel(std::time::Instant::now());
+    let mut input_activity = InputActivityListener { activity_tx };
+    tokio::spawn(async move {
+        input_activity.start().await;
+    });
+
+    let (refresh_tx, refresh_rx) = tokio::sync::mpsc::channel(10);
+    let (refresh_policy_tx, refresh_policy_rx) = tokio::sync::watch::channel(Default::default());
+    let (refresh_stats_tx, refresh_stats_rx) = tokio::sync::watch::channel(String::new());
+    let mut refresh_manager = RefreshManager {
+        refresh_rx,
+        policy_rx: refresh_policy_rx,
+        input_rx: activity_rx.clone(),
+        stats_tx: refresh_stats_tx,
+    };
+    tokio::spawn(async move {
+        refresh_manager.start().await;
+    });
+
+    let mut refresh_stats_listener = RefreshStatsListener {
+        stats_rx: refresh_stats_rx,
+    };
+    tokio::spawn(async move {
+        let mut socket = refresh_stats_listener.open_socket().await;
+        refresh_stats_listener.start(&mut socket).await;
+    });
+
+    let (pen_tx, pen_rx) = tokio::sync::watch::channel(Default::default());
+    let mut pen_listener = PenListener {
+        config: config.pen.clone(),
+        pen_tx,
+    };
+    tokio::spawn(async move {
+        pen_listener.start().await;
+    });
+
+    let mut pen_topic_listener = PenTopicListener {
+        pen_rx: pen_rx.clone(),
+    };
+    tokio::spawn(async move {
+        let mut socket = pen_topic_listener.open_socket().await;
+        pen_topic_listener.start(&mut socket).await;
+    });
+
+    let (preset_tx, preset_rx) = tokio::sync::watch::channel(String::new());
     let mut eink = EinkListener {
         channel_rx: tx.subscribe(),
         window_settings: true,
-        // gamma_channel_tx: gamma_channel_tx,
+        presets: Vec::new(),
+        active_preset: None,
+        preset_tx,
+        current_mode: Default::default(),
+        mode_override: None,
+        focus_rx,
+        input_rx: activity_rx,
+        refresh_tx,
+        refresh_policy_tx,
+        last_error: None,
+        sliders: config.sliders.clone(),
+        config_rx: config_rx.clone(),
+        gamma_channel_tx,
+        gamma: None,
+        pen: config.pen.clone(),
+        pen_rx: pen_rx.clone(),
+        windows: Vec::new(),
+        windows_modified: None,
+        focused_app: None,
     };
     tokio::spawn(async move {
         eink.start().await;
     });
 
+    let mut eink_preset_listener = EinkPresetListener { preset_rx };
+    tokio::spawn(async move {
+        let mut socket = eink_preset_listener.open_socket().await;
+        eink_preset_listener.start(&mut socket).await;
+    });
+
     let mut settingsmenu = SettingsMenuListener {
         channel_rx: tx.subscribe(),
         channel_tx: tx.clone(),
@@ -92,9 +211,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         settingsmenu.start().await;
     });
 
-    let channel = tokio::sync::mpsc::channel(10);
+    let (battery_tx, battery_rx) = tokio::sync::watch::channel(Default::default());
+    let mut power_supply_monitor = PowerSupplyMonitor {
+        power_supply_dir: POWER_SUPPLY_DIR.into(),
+        uevents: UeventSubscription::new(&uevent_tx, "power_supply"),
+        battery_tx,
+    };
+    tokio::spawn(async move {
+        power_supply_monitor.start().await;
+    });
+
+    let mut battery_listener = BatteryListener {
+        battery_rx: battery_rx.clone(),
+    };
+    tokio::spawn(async move {
+        let mut socket = battery_listener.open_socket().await;
+        battery_listener.start(&mut socket).await;
+    });
+
     let mut battery_state = BatteryStateListener {
-        channel_tx: channel.0,
+        battery_rx: battery_rx.clone(),
     };
     tokio::spawn(async move {
         let mut socket = battery_state.open_socket().await;
@@ -102,26 +238,128 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     let mut battery_percent = BatteryPercentListener {
-        channel_rx: channel.1,
+        battery_rx: battery_rx.clone(),
     };
     tokio::spawn(async move {
         let mut socket = battery_percent.open_socket().await;
         battery_percent.start(&mut socket).await;
     });
 
+    // Frontlight changes and limits from the provider itself
+    let (frontlight_tx, frontlight_rx) = tokio::sync::mpsc::channel(10);
+    let mut low_battery = LowBatteryListener {
+        config: config.low_battery.clone(),
+        channel_tx: tx.clone(),
+        frontlight_tx: frontlight_tx.clone(),
+        battery_rx: battery_rx.clone(),
+    };
+    tokio::spawn(async move {
+        low_battery.start().await;
+    });
+
+    let (history_tx, history_rx) = tokio::sync::watch::channel(String::new());
+    let mut battery_history = BatteryHistoryListener {
+        channel_rx: tx.subscribe(),
+        history_tx,
+        file: HistoryFile::at(&HistoryFile::default_path()),
+        battery_rx,
+    };
+    tokio::spawn(async move {
+        battery_history.start().await;
+    });
+
+    let mut battery_history_topic = BatteryHistoryTopicListener { history_rx };
+    tokio::spawn(async move {
+        let mut socket = battery_history_topic.open_socket().await;
+        battery_history_topic.start(&mut socket).await;
+    });
+
     let mut bluetooth_listener = BluetoothListener;
     tokio::spawn(async move {
         let mut socket = bluetooth_listener.open_socket().await;
         bluetooth_listener.start(&mut socket).await;
     });
 
-    let mut backlight_listener = CoolBacklightListener;
+    let sliders_json = serde_json::to_string(&config.sliders).unwrap_or_default();
+    let (sliders_tx, sliders_rx) = tokio::sync::watch::channel(sliders_json);
+    let mut config_listener = ConfigListener {
+        channel_rx: tx.subscribe(),
+        config_tx,
+        sliders_tx,
+    };
+    tokio::spawn(async move {
+        config_listener.start().await;
+    });
+
+    let mut sliders_listener = SlidersListener { sliders_rx };
+    tokio::spawn(async move {
+        let mut socket = sliders_listener.open_socket().await;
+        sliders_listener.start(&mut socket).await;
+    });
+
+    let mut backlight_controller = BacklightController {
+        channel_rx: tx.subscribe(),
+        internal_channel_rx: frontlight_rx,
+        config: config.backlight.clone(),
+        fades: Default::default(),
+        temperature: 0.5,
+        limit: None,
+    };
+    tokio::spawn(async move {
+        backlight_controller.start().await;
+    });
+
+    let mut frontlight_scheduler = FrontlightScheduler {
+        config: config.schedule.clone(),
+        channel_rx: tx.subscribe(),
+        frontlight_tx: frontlight_tx.clone(),
+        override_until: None,
+        last_set: (None, None),
+    };
+    tokio::spawn(async move {
+        frontlight_scheduler.start().await;
+    });
+
+    let mut auto_brightness = AutoBrightness {
+        config: config.auto_brightness.clone(),
+        channel_rx: tx.subscribe(),
+        frontlight_tx,
+        enabled: config.auto_brightness.enabled,
+        curve: Vec::new(),
+        last_set: None,
+        hold_lux: None,
+        fade: std::time::Duration::from_millis(config.backlight.fade_ms as u64),
+    };
+    tokio::spawn(async move {
+        auto_brightness.start().await;
+    });
+
+    let mut frontlight_listener = FrontlightListener {
+        uevents: UeventSubscription::new(&uevent_tx, "backlight"),
+        config_rx: config_rx.clone(),
+    };
+    tokio::spawn(async move {
+        let mut socket = frontlight_listener.open_socket().await;
+        frontlight_listener.start(&mut socket).await;
+    });
+
+    let mut backlight_listener = CoolBacklightListener {
+        brightness: ChannelBrightness::new(
+            UeventSubscription::new(&uevent_tx, "backlight"),
+            config_rx.clone(),
+        ),
+    };
     tokio::spawn(async move {
         let mut socket = backlight_listener.open_socket().await;
         backlight_listener.start(&mut socket).await;
     });
 
-    let mut backlight_warm_listener = WarmBacklightListener;
+    let mut backlight_warm_listener = WarmBacklightListener {
+        brightness: ChannelBrightness::new(
+            UeventSubscription::new(&uevent_tx, "backlight"),
+            config_rx.clone(),
+        ),
+    };
     tokio::spawn(async move {
         let mut socket = backlight_warm_listener.open_socket().await;
         backlight_warm_listener.start(&mut socket).await;
@@ -139,13 +377,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         network_listener.start(&mut socket).await;
     });
 
-    let mut volume_listener = VolumeListener;
+    let mut volume_listener = VolumeListener {
+        config_rx: config_rx.clone(),
+    };
     tokio::spawn(async move {
         let mut socket = volume_listener.open_socket().await;
         volume_listener.start(&mut socket).await;
     });
 
-    let mut gestures_manager = GesturesManager::default();
+    let (orientation_tx, orientation_rx) = tokio::sync::watch::channel(Default::default());
+    let mut orientation_listener = OrientationListener {
+        config: config.orientation.clone(),
+        orientation_tx,
+    };
+    tokio::spawn(async move {
+        orientation_listener.start().await;
+    });
+
+    let mut orientation_topic_listener = OrientationTopicListener {
+        orientation_rx: orientation_rx.clone(),
+    };
+    tokio::spawn(async move {
+        let mut socket = orientation_topic_listener.open_socket().await;
+        orientation_topic_listener.start(&mut socket).await;
+    });
+
+    let mut gestures_manager = GesturesManager {
+        config_rx,
+        orientation_rx,
+        request_tx: tx.clone(),
+        pen_rx,
+    };
     tokio::spawn(async move {
         gestures_manager.start().await;
     });
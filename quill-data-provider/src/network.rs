@@ -1,12 +1,10 @@
 use async_trait::async_trait;
 use log::*;
 use serde::{Deserialize, Serialize};
-use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
-};
+use tokio::process::Command;
 
 use crate::listener::SocketHandler;
+use crate::process::ManagedProcess;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct NetworkInfo {
@@ -84,21 +82,12 @@ impl SocketHandler for NetworkListener {
         let mut previous_network_info = get_network_info().await;
         self.send_unix(unix, previous_network_info.clone()).await;
 
-        let mut cmd = Command::new("ip")
-            .arg("monitor")
-            .arg("link")
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn ip monitor link command");
+        let mut ip_monitor = ManagedProcess::new("ip")
+            .args(["monitor", "link"])
+            .forward_stdout()
+            .spawn();
 
-        let stdout = cmd.stdout.take().expect("Failed to take stdout");
-        let mut reader = BufReader::new(stdout).lines();
-
-        while let Some(_line) = reader
-            .next_line()
-            .await
-            .expect("Failed to read line from ip monitor link")
-        {
+        while let Some(_line) = ip_monitor.next_line().await {
             // debug!("ip monitor link line: {}", line);
             let current_network_info = get_network_info().await;
             if previous_network_info != current_network_info {
@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use quill_data_provider_lib::run_cmd;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::watch, time::sleep};
+
+use crate::listener::SocketHandler;
+
+// The output transform, counter-clockwise like in wayland. Flips are ignored
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Orientation {
+    // The names of niri's config and output command, or those of its json
+    pub fn from_niri(transform: &str) -> Option<Self> {
+        match transform {
+            "normal" | "flipped" | "Normal" | "Flipped" => Some(Orientation::Normal),
+            "90" | "flipped-90" | "Flipped90" => Some(Orientation::Rotate90),
+            "180" | "flipped-180" | "Flipped180" => Some(Orientation::Rotate180),
+            "270" | "flipped-270" | "Flipped270" => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+
+    pub fn to_niri(self) -> &'static str {
+        match self {
+            Orientation::Normal => "normal",
+            Orientation::Rotate90 => "90",
+            Orientation::Rotate180 => "180",
+            Orientation::Rotate270 => "270",
+        }
+    }
+
+    // A position on the panel, both axes from 0 to 1, as the user sees it on the screen
+    pub fn to_screen(self, (x, y): (f32, f32)) -> (f32, f32) {
+        match self {
+            Orientation::Normal => (x, y),
+            Orientation::Rotate90 => (1.0 - y, x),
+            Orientation::Rotate180 => (1.0 - x, 1.0 - y),
+            Orientation::Rotate270 => (y, 1.0 - x),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrientationConfig {
+    pub output: String,
+    // Rotates the output to follow the accelerometer
+    pub auto_rotate: bool,
+    pub accelerometer: String,
+    pub poll_ms: u32,
+}
+
+impl Default for OrientationConfig {
+    fn default() -> Self {
+        Self {
+            output: "DPI-1".to_string(),
+            auto_rotate: false,
+            accelerometer: "/sys/bus/iio/devices/iio:device0".to_string(),
+            poll_ms: 2000,
+        }
+    }
+}
+
+// The compositor's transform is what counts, the accelerometer only changes it when
+// auto rotating. niri has no output events, so it's polled
+pub struct OrientationListener {
+    pub config: OrientationConfig,
+    pub orientation_tx: watch::Sender<Orientation>,
+}
+
+impl OrientationListener {
+    pub async fn start(&mut self) {
+        info!("Starting OrientationListener");
+        loop {
+            if self.config.auto_rotate
+                && let Some(orientation) = self.read_accelerometer().await
+            {
+                let current = *self.orientation_tx.borrow();
+                if orientation != current {
+                    self.rotate_output(orientation).await;
+                }
+            }
+            match self.read_transform().await {
+                Some(orientation) => {
+                    self.orientation_tx.send_if_modified(|current| {
+                        let changed = *current != orientation;
+                        *current = orientation;
+                        changed
+                    });
+                }
+                None => debug!("No transform for output {}", self.config.output),
+            }
+            sleep(Duration::from_millis(self.config.poll_ms.max(100) as u64)).await;
+        }
+    }
+
+    async fn read_transform(&self) -> Option<Orientation> {
+        let outputs = run_cmd("niri msg --json outputs").await;
+        let outputs: Value = serde_json::from_str(&outputs)
+            .inspect_err(|e| warn!("Failed to parse niri outputs: {}", e))
+            .ok()?;
+        let transform = outputs
+            .get(&self.config.output)?
+            .get("logical")?
+            .get("transform")?
+            .as_str()?;
+        Orientation::from_niri(transform)
+    }
+
+    async fn rotate_output(&self, orientation: Orientation) {
+        info!("Rotating {} to {:?}", self.config.output, orientation);
+        run_cmd(&format!(
+            "niri msg output {} transform {}",
+            self.config.output,
+            orientation.to_niri()
+        ))
+        .await;
+    }
+
+    // None while the device lies flat or the sensor can't be read
+    async fn read_accelerometer(&self) -> Option<Orientation> {
+        let read_axis = |axis: &str| {
+            let path = format!("{}/in_accel_{}_raw", self.config.accelerometer, axis);
+            async move {
+                let value = tokio::fs::read_to_string(&path)
+                    .await
+                    .inspect_err(|e| error!("Failed to read {}: {}", path, e))
+                    .ok()?;
+                value.trim().parse::<f32>().ok()
+            }
+        };
+        let x = read_axis("x").await?;
+        let y = read_axis("y").await?;
+        orientation_from_gravity(x, y)
+    }
+}
+
+// Gravity along the panel axes. One axis has to clearly win, so a tilted device
+// doesn't flip back and forth
+pub fn orientation_from_gravity(x: f32, y: f32) -> Option<Orientation> {
+    const DOMINANCE: f32 = 1.5;
+    if y.abs() > x.abs() * DOMINANCE {
+        Some(if y < 0.0 {
+            Orientation::Normal
+        } else {
+            Orientation::Rotate180
+        })
+    } else if x.abs() > y.abs() * DOMINANCE {
+        Some(if x > 0.0 {
+            Orientation::Rotate90
+        } else {
+            Orientation::Rotate270
+        })
+    } else {
+        None
+    }
+}
+
+pub struct OrientationTopicListener {
+    pub orientation_rx: watch::Receiver<Orientation>,
+}
+
+#[async_trait]
+impl SocketHandler for OrientationTopicListener {
+    const SOCKET_NAME: &'static str = "orientation";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting OrientationTopicListener");
+        loop {
+            let orientation = *self.orientation_rx.borrow_and_update();
+            match serde_json::to_string(&orientation) {
+                Ok(json) => self.send_unix(unix, json).await,
+                Err(e) => error!("Failed to serialize orientation: {}", e),
+            }
+            if self.orientation_rx.changed().await.is_err() {
+                error!("Orientation sender dropped");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn niri_config_names() {
+        for (transform, orientation) in [
+            ("normal", Orientation::Normal),
+            ("90", Orientation::Rotate90),
+            ("180", Orientation::Rotate180),
+            ("270", Orientation::Rotate270),
+            ("flipped", Orientation::Normal),
+            ("flipped-90", Orientation::Rotate90),
+            ("flipped-180", Orientation::Rotate180),
+            ("flipped-270", Orientation::Rotate270),
+        ] {
+            assert_eq!(
+                Orientation::from_niri(transform),
+                Some(orientation),
+                "{}",
+                transform
+            );
+        }
+    }
+
+    #[test]
+    fn niri_json_names() {
+        for (transform, orientation) in [
+            ("Normal", Orientation::Normal),
+            ("Flipped", Orientation::Normal),
+            ("Flipped90", Orientation::Rotate90),
+            ("Flipped180", Orientation::Rotate180),
+            ("Flipped270", Orientation::Rotate270),
+        ] {
+            assert_eq!(
+                Orientation::from_niri(transform),
+                Some(orientation),
+                "{}",
+                transform
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_transforms() {
+        for transform in [
+            "",
+            "flipped90",
+            "Flipped-90",
+            "flipped-45",
+            "-90",
+            "upside down",
+        ] {
+            assert_eq!(Orientation::from_niri(transform), None, "{}", transform);
+        }
+    }
+
+    #[test]
+    fn set_orientations_read_back() {
+        for orientation in [
+            Orientation::Normal,
+            Orientation::Rotate90,
+            Orientation::Rotate180,
+            Orientation::Rotate270,
+        ] {
+            assert_eq!(
+                Orientation::from_niri(orientation.to_niri()),
+                Some(orientation)
+            );
+        }
+    }
+}
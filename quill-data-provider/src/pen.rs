@@ -0,0 +1,305 @@
+// The stylus, read straight from its event device. While it's in proximity the hand
+// usually rests on the screen, so touch gestures are ignored then
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncReadExt, sync::watch, time::sleep};
+
+use crate::input::{
+    BTN_STYLUS, BTN_STYLUS2, BTN_TOOL_PEN, BTN_TOOL_RUBBER, BTN_TOUCH, EV_KEY, EV_SYN,
+    INPUT_EVENT_SIZE, InputEvent, SYN_REPORT, find_device_with_key, parse_events,
+};
+use crate::listener::SocketHandler;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PenState {
+    pub in_proximity: bool,
+    // The other end of the pen is used
+    pub eraser: bool,
+    pub touching: bool,
+    pub button: bool,
+    pub button2: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PenConfig {
+    pub enabled: bool,
+    // The first device with a pen tool when not set
+    pub device: Option<String>,
+    // A preset name or a DriverMode in ron, used while the pen is in proximity
+    pub drawing_preset: Option<String>,
+    // How long the pen has to be away before the drawing preset is left
+    pub away_ms: u32,
+    // Ignore touch gestures while the pen is in proximity
+    pub palm_rejection: bool,
+}
+
+impl Default for PenConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            device: None,
+            drawing_preset: None,
+            away_ms: 1500,
+            palm_rejection: true,
+        }
+    }
+}
+
+// Fed one event at a time, the state changes with every report
+#[derive(Default)]
+pub struct PenTracker {
+    pen_tool: bool,
+    rubber_tool: bool,
+    pending: PenState,
+    current: PenState,
+}
+
+impl PenTracker {
+    pub fn feed(&mut self, event: &InputEvent) -> Option<PenState> {
+        let pressed = event.value != 0;
+        match (event.event_type, event.code) {
+            (EV_KEY, BTN_TOOL_PEN) => self.pen_tool = pressed,
+            (EV_KEY, BTN_TOOL_RUBBER) => self.rubber_tool = pressed,
+            (EV_KEY, BTN_TOUCH) => self.pending.touching = pressed,
+            (EV_KEY, BTN_STYLUS) => self.pending.button = pressed,
+            (EV_KEY, BTN_STYLUS2) => self.pending.button2 = pressed,
+            (EV_SYN, SYN_REPORT) => {
+                self.pending.in_proximity = self.pen_tool || self.rubber_tool;
+                self.pending.eraser = self.rubber_tool;
+                if self.pending != self.current {
+                    self.current = self.pending;
+                    return Some(self.current);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+pub struct PenListener {
+    pub config: PenConfig,
+    pub pen_tx: watch::Sender<PenState>,
+}
+
+impl PenListener {
+    pub async fn start(&mut self) {
+        if !self.config.enabled {
+            info!("Pen is disabled");
+            return;
+        }
+        info!("Starting PenListener");
+        // The digitizer can come and go, like over a suspend
+        loop {
+            if let Err(e) = self.watch_device().await {
+                warn!("Pen device: {}", e);
+            }
+            // A pen which went away with its device isn't in proximity anymore
+            self.pen_tx.send_if_modified(|state| {
+                let changed = *state != PenState::default();
+                *state = PenState::default();
+                changed
+            });
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn watch_device(&self) -> std::io::Result<()> {
+        let path = match &self.config.device {
+            Some(device) => device.into(),
+            // Opens every event device
+            None => tokio::task::spawn_blocking(|| find_device_with_key(BTN_TOOL_PEN))
+                .await
+                .map_err(std::io::Error::other)?
+                .ok_or(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "no device with a pen tool",
+                ))?,
+        };
+        let mut file = tokio::fs::File::open(&path).await?;
+        debug!("Reading the pen from {:?}", path);
+
+        let mut tracker = PenTracker::default();
+        let mut buf = [0u8; INPUT_EVENT_SIZE * 64];
+        loop {
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            for event in parse_events(&buf[..read]) {
+                if let Some(state) = tracker.feed(&event) {
+                    debug!("Pen state: {:?}", state);
+                    self.pen_tx.send_replace(state);
+                }
+            }
+        }
+    }
+}
+
+pub struct PenTopicListener {
+    pub pen_rx: watch::Receiver<PenState>,
+}
+
+#[async_trait]
+impl SocketHandler for PenTopicListener {
+    const SOCKET_NAME: &'static str = "pen";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting PenTopicListener");
+        loop {
+            let state = *self.pen_rx.borrow_and_update();
+            match serde_json::to_string(&state) {
+                Ok(json) => self.send_unix(unix, json).await,
+                Err(e) => error!("Failed to serialize the pen state: {}", e),
+            }
+            if self.pen_rx.changed().await.is_err() {
+                error!("Pen sender dropped");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: u16, value: i32) -> InputEvent {
+        InputEvent {
+            time: Duration::ZERO,
+            event_type: EV_KEY,
+            code,
+            value,
+        }
+    }
+
+    fn report() -> InputEvent {
+        InputEvent {
+            time: Duration::ZERO,
+            event_type: EV_SYN,
+            code: SYN_REPORT,
+            value: 0,
+        }
+    }
+
+    // The states reported, one per frame which changed something
+    fn feed(tracker: &mut PenTracker, events: &[InputEvent]) -> Vec<PenState> {
+        events
+            .iter()
+            .filter_map(|event| tracker.feed(event))
+            .collect()
+    }
+
+    #[test]
+    fn proximity_and_touch() {
+        let mut tracker = PenTracker::default();
+        let near = PenState {
+            in_proximity: true,
+            ..Default::default()
+        };
+        let touching = PenState {
+            touching: true,
+            ..near
+        };
+        assert_eq!(
+            feed(
+                &mut tracker,
+                &[
+                    key(BTN_TOOL_PEN, 1),
+                    report(),
+                    key(BTN_TOUCH, 1),
+                    report(),
+                    // Nothing changed, nothing reported
+                    report(),
+                    key(BTN_TOUCH, 0),
+                    report(),
+                    key(BTN_TOOL_PEN, 0),
+                    report(),
+                ]
+            ),
+            [near, touching, near, PenState::default()]
+        );
+    }
+
+    #[test]
+    fn eraser() {
+        let mut tracker = PenTracker::default();
+        let erasing = PenState {
+            in_proximity: true,
+            eraser: true,
+            touching: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            feed(
+                &mut tracker,
+                &[key(BTN_TOOL_RUBBER, 1), key(BTN_TOUCH, 1), report()]
+            ),
+            [erasing]
+        );
+        // Turned around without leaving, still in proximity
+        assert_eq!(
+            feed(
+                &mut tracker,
+                &[
+                    key(BTN_TOUCH, 0),
+                    key(BTN_TOOL_RUBBER, 0),
+                    key(BTN_TOOL_PEN, 1),
+                    report()
+                ]
+            ),
+            [PenState {
+                in_proximity: true,
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn buttons() {
+        let mut tracker = PenTracker::default();
+        let states = feed(
+            &mut tracker,
+            &[
+                key(BTN_TOOL_PEN, 1),
+                key(BTN_STYLUS, 1),
+                report(),
+                key(BTN_STYLUS, 0),
+                key(BTN_STYLUS2, 1),
+                report(),
+                key(BTN_STYLUS2, 0),
+                report(),
+            ],
+        );
+        let near = PenState {
+            in_proximity: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            states,
+            [
+                PenState {
+                    button: true,
+                    ..near
+                },
+                PenState {
+                    button2: true,
+                    ..near
+                },
+                near
+            ]
+        );
+    }
+
+    #[test]
+    fn only_reports_change_the_state() {
+        let mut tracker = PenTracker::default();
+        assert_eq!(feed(&mut tracker, &[key(BTN_TOOL_PEN, 1)]), []);
+        assert_eq!(tracker.feed(&report()).map(|s| s.in_proximity), Some(true));
+    }
+}
@@ -1,12 +1,9 @@
 use async_trait::async_trait;
 use log::*;
 use serde::{Deserialize, Serialize};
-use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
-};
 
 use crate::listener::SocketHandler;
+use crate::process::ManagedProcess;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -104,20 +101,14 @@ impl SocketHandler for PlayerListener {
     async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
         info!("Starting PlayerListener");
 
-        let mut cmd = Command::new("playerctl")
-            .arg("metadata")
-            .arg("-F")
-            .arg("-f")
-            .arg(r#"{"name":"{{playerName}}","title":"{{title}}","artist":"{{artist}}","artUrl":"{{mpris:artUrl}}","status":"{{status}}","length":"{{mpris:length}}"}"#)
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn playerctl monitor command");
-
-        let stdout = cmd.stdout.take().expect("Failed to take stdout");
-        let mut reader = BufReader::new(stdout).lines();
+        let mut playerctl = ManagedProcess::new("playerctl")
+            .args(["metadata", "-F", "-f"])
+            .args([r#"{"name":"{{playerName}}","title":"{{title}}","artist":"{{artist}}","artUrl":"{{mpris:artUrl}}","status":"{{status}}","length":"{{mpris:length}}"}"#])
+            .forward_stdout()
+            .spawn();
 
         let mut previous_player_info = String::new();
-        if let Some(initial) = reader.next_line().await.unwrap_or(None) {
+        if let Some(initial) = playerctl.next_line().await {
             if !initial.is_empty() {
                 let initial_state = process_player_metadata(&initial).await;
                 self.send_unix(unix, initial_state.clone()).await;
@@ -125,11 +116,7 @@ impl SocketHandler for PlayerListener {
             }
         }
 
-        while let Some(line) = reader
-            .next_line()
-            .await
-            .expect("Failed to read line from playerctl monitor")
-        {
+        while let Some(line) = playerctl.next_line().await {
             let current_player_info = process_player_metadata(&line).await;
             if previous_player_info != current_player_info {
                 self.send_unix(unix, current_player_info.clone()).await;
@@ -0,0 +1,207 @@
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::sleep,
+};
+
+// A run this long counts as healthy, the next crash restarts quickly again
+const BACKOFF_RESET: Duration = Duration::from_secs(30);
+
+// A child process which is restarted when it dies, with a growing delay so a broken
+// command doesn't spin. Output goes to the log, or stdout to the handle with forward_stdout
+pub struct ManagedProcess {
+    name: String,
+    program: String,
+    args: Vec<String>,
+    forward_stdout: bool,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    stop_timeout: Duration,
+}
+
+impl ManagedProcess {
+    pub fn new(program: &str) -> Self {
+        Self {
+            name: program.to_string(),
+            program: program.to_string(),
+            args: Vec::new(),
+            forward_stdout: false,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            stop_timeout: Duration::from_secs(2),
+        }
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.args
+            .extend(args.into_iter().map(|a| a.as_ref().to_string()));
+        self
+    }
+
+    // Stdout lines go to ProcessHandle::next_line, over restarts too
+    pub fn forward_stdout(mut self) -> Self {
+        self.forward_stdout = true;
+        self
+    }
+
+    pub fn spawn(self) -> ProcessHandle {
+        let (lines_tx, lines_rx) = mpsc::channel(64);
+        let (pid_tx, pid_rx) = watch::channel(None);
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let name = self.name.clone();
+        let task = tokio::spawn(self.supervise(lines_tx, pid_tx, stop_rx));
+        ProcessHandle {
+            name,
+            lines_rx,
+            pid_rx,
+            stop_tx: Some(stop_tx),
+            task,
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true);
+        command
+    }
+
+    async fn supervise(
+        self,
+        lines_tx: mpsc::Sender<String>,
+        pid_tx: watch::Sender<Option<u32>>,
+        mut stop_rx: oneshot::Receiver<()>,
+    ) {
+        let mut backoff = self.min_backoff;
+        loop {
+            let started = Instant::now();
+            match self.command().spawn() {
+                Ok(mut child) => {
+                    info!("Started {} with pid {:?}", self.name, child.id());
+                    pid_tx.send_replace(child.id());
+                    if let Some(stdout) = child.stdout.take() {
+                        let lines_tx = self.forward_stdout.then(|| lines_tx.clone());
+                        tokio::spawn(read_lines(self.name.clone(), stdout, lines_tx, false));
+                    }
+                    if let Some(stderr) = child.stderr.take() {
+                        tokio::spawn(read_lines(self.name.clone(), stderr, None, true));
+                    }
+                    tokio::select! {
+                        status = child.wait() => match status {
+                            Ok(status) => warn!("{} exited with {}", self.name, status),
+                            Err(e) => error!("Failed to wait for {}: {}", self.name, e),
+                        },
+                        _ = &mut stop_rx => {
+                            self.terminate(&mut child).await;
+                            pid_tx.send_replace(None);
+                            return;
+                        }
+                    }
+                    pid_tx.send_replace(None);
+                }
+                Err(e) => error!("Failed to start {}: {}", self.name, e),
+            }
+
+            if started.elapsed() >= BACKOFF_RESET {
+                backoff = self.min_backoff;
+            }
+            debug!("Restarting {} in {:?}", self.name, backoff);
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = &mut stop_rx => return,
+            }
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    // SIGTERM first, so it can clean up (gammastep resets the gamma), then kill
+    async fn terminate(&self, child: &mut Child) {
+        if let Some(pid) = child.id() {
+            // Safety: only sends a signal to our own child
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+            if tokio::time::timeout(self.stop_timeout, child.wait())
+                .await
+                .is_ok()
+            {
+                debug!("{} stopped", self.name);
+                return;
+            }
+            warn!("{} ignored SIGTERM, killing it", self.name);
+        }
+        if let Err(e) = child.kill().await {
+            error!("Failed to kill {}: {}", self.name, e);
+        }
+    }
+}
+
+async fn read_lines<R: AsyncRead + Unpin>(
+    name: String,
+    output: R,
+    lines_tx: Option<mpsc::Sender<String>>,
+    is_stderr: bool,
+) {
+    let mut reader = BufReader::new(output).lines();
+    while let Ok(Some(line)) = reader.next_line().await {
+        match &lines_tx {
+            Some(lines_tx) => {
+                if lines_tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+            None if is_stderr => warn!("{}: {}", name, line),
+            None => debug!("{}: {}", name, line),
+        }
+    }
+}
+
+// Dropping the handle kills the process, stop() lets it exit first
+pub struct ProcessHandle {
+    name: String,
+    lines_rx: mpsc::Receiver<String>,
+    pid_rx: watch::Receiver<Option<u32>>,
+    stop_tx: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl ProcessHandle {
+    // None once the process is stopped for good
+    pub async fn next_line(&mut self) -> Option<String> {
+        self.lines_rx.recv().await
+    }
+
+    // None while it's restarting
+    pub fn pid(&self) -> Option<u32> {
+        *self.pid_rx.borrow()
+    }
+
+    // Consumes the handle, the supervisor task is only awaited once
+    pub async fn stop(mut self) {
+        debug!("Stopping {} with pid {:?}", self.name, self.pid());
+        if let Some(stop_tx) = self.stop_tx.take() {
+            stop_tx.send(()).ok();
+        }
+        if let Err(e) = (&mut self.task).await {
+            error!("Supervisor of {} failed: {}", self.name, e);
+        }
+    }
+}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
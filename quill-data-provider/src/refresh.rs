@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::{debug, error, info};
+use quill_data_provider_lib::RefreshPolicy;
+use serde::Serialize;
+use tokio::sync::{mpsc, watch};
+
+use crate::eink::refresh_screen;
+use crate::listener::SocketHandler;
+
+// Input closer together than this is counted as the same partial update
+const UPDATE_GAP: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum RefreshReason {
+    Request,
+    OverrideEnd,
+    FastToNormal,
+    PartialUpdates,
+    Idle,
+    Interval,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+struct RefreshStats {
+    partial_updates: u32,
+    global_refreshes: u64,
+    last_reason: Option<RefreshReason>,
+}
+
+// Every global refresh goes through here, so the counters stay right.
+// The driver does not report partial updates, so they are estimated from input bursts
+pub struct RefreshManager {
+    pub refresh_rx: mpsc::Receiver<RefreshReason>,
+    pub policy_rx: watch::Receiver<RefreshPolicy>,
+    pub input_rx: watch::Receiver<Instant>,
+    pub stats_tx: watch::Sender<String>,
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn after_secs(from: Instant, secs: u32) -> Option<Instant> {
+    (secs > 0).then(|| from + Duration::from_secs(secs as u64))
+}
+
+impl RefreshManager {
+    pub async fn start(&mut self) {
+        info!("Starting RefreshManager");
+        let mut stats = RefreshStats::default();
+        let mut policy = *self.policy_rx.borrow_and_update();
+        let mut last_refresh = Instant::now();
+        let mut last_input = *self.input_rx.borrow_and_update();
+        // Set while an input burst is going on
+        let mut update_end: Option<Instant> = None;
+        self.publish(&stats);
+
+        loop {
+            // Idle and interval only make sense if something was drawn since the last refresh
+            let dirty = stats.partial_updates > 0 || update_end.is_some();
+            let idle_deadline = after_secs(last_input, policy.idle_secs).filter(|_| dirty);
+            let interval_deadline =
+                after_secs(last_refresh, policy.interval_secs).filter(|_| dirty);
+
+            let reason = tokio::select! {
+                reason = self.refresh_rx.recv() => {
+                    match reason {
+                        Some(reason) => Some(reason),
+                        None => {
+                            error!("Refresh channel closed");
+                            break;
+                        }
+                    }
+                }
+                Ok(()) = self.policy_rx.changed() => {
+                    policy = *self.policy_rx.borrow_and_update();
+                    debug!("Refresh policy is now: {:?}", policy);
+                    None
+                }
+                Ok(()) = self.input_rx.changed() => {
+                    last_input = *self.input_rx.borrow_and_update();
+                    update_end = Some(last_input + UPDATE_GAP);
+                    None
+                }
+                _ = sleep_until(update_end) => {
+                    update_end = None;
+                    stats.partial_updates += 1;
+                    self.publish(&stats);
+                    (policy.after_updates > 0 && stats.partial_updates >= policy.after_updates)
+                        .then_some(RefreshReason::PartialUpdates)
+                }
+                _ = sleep_until(idle_deadline) => Some(RefreshReason::Idle),
+                _ = sleep_until(interval_deadline) => Some(RefreshReason::Interval),
+            };
+
+            if let Some(reason) = reason {
+                debug!("Global refresh because of {:?}", reason);
+                refresh_screen().await;
+                last_refresh = Instant::now();
+                stats.partial_updates = 0;
+                stats.global_refreshes += 1;
+                stats.last_reason = Some(reason);
+                self.publish(&stats);
+            }
+        }
+    }
+
+    fn publish(&self, stats: &RefreshStats) {
+        match serde_json::to_string(stats) {
+            Ok(json) => {
+                self.stats_tx.send_replace(json);
+            }
+            Err(e) => error!("Failed to serialize refresh stats: {}", e),
+        }
+    }
+}
+
+pub struct RefreshStatsListener {
+    pub stats_rx: watch::Receiver<String>,
+}
+
+#[async_trait]
+impl SocketHandler for RefreshStatsListener {
+    const SOCKET_NAME: &'static str = "eink_refresh";
+
+    async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
+        info!("Starting RefreshStatsListener");
+        let mut stats_rx = self.stats_rx.clone();
+        self.forward_watch(unix, &mut stats_rx).await;
+    }
+}
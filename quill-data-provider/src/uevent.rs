@@ -0,0 +1,290 @@
+// Kernel uevents read from a netlink socket, the same ones udevadm monitor shows. One socket
+// for the whole provider, listeners subscribe to the subsystems they care about
+
+use std::{
+    collections::HashMap,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+use tokio::{
+    io::{Interest, unix::AsyncFd},
+    sync::broadcast,
+    time::sleep,
+};
+
+// The kernel's multicast group, udev's own is 2
+const KERNEL_GROUP: u32 = 1;
+const RECEIVE_BUFFER: usize = 1024 * 1024;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Uevent {
+    // add, remove, change, ...
+    pub action: String,
+    pub devpath: String,
+    pub subsystem: String,
+    pub properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    // "action@devpath" and then KEY=VALUE lines, all ending with a null byte
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let mut fields = message
+            .split(|byte| *byte == 0)
+            .filter(|field| !field.is_empty())
+            .map(String::from_utf8_lossy);
+        let header = fields.next()?;
+        let (action, devpath) = header.split_once('@')?;
+        let properties: HashMap<String, String> = fields
+            .filter_map(|field| {
+                let (key, value) = field.split_once('=')?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+        Some(Uevent {
+            action: action.to_string(),
+            devpath: devpath.to_string(),
+            subsystem: properties.get("SUBSYSTEM").cloned().unwrap_or_default(),
+            properties,
+        })
+    }
+}
+
+// Sends a message as if it came from the kernel, for trying listeners without the hardware
+pub fn inject_uevent(uevent_tx: &broadcast::Sender<Uevent>, message: &[u8]) -> bool {
+    match Uevent::parse(message) {
+        Some(uevent) => uevent_tx.send(uevent).is_ok(),
+        None => {
+            warn!("Not a uevent: {:?}", String::from_utf8_lossy(message));
+            false
+        }
+    }
+}
+
+// The events of one subsystem
+pub struct UeventSubscription {
+    pub rx: broadcast::Receiver<Uevent>,
+    pub subsystem: &'static str,
+}
+
+impl UeventSubscription {
+    pub fn new(uevent_tx: &broadcast::Sender<Uevent>, subsystem: &'static str) -> Self {
+        UeventSubscription {
+            rx: uevent_tx.subscribe(),
+            subsystem,
+        }
+    }
+
+    // Missed events come as one change of the whole subsystem, so the reader reads it all
+    // again. None once the socket is gone for good
+    pub async fn next(&mut self) -> Option<Uevent> {
+        loop {
+            match self.rx.recv().await {
+                Ok(uevent) if uevent.subsystem == self.subsystem => return Some(uevent),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!("Missed {} uevents for {}", missed, self.subsystem);
+                    return Some(Uevent {
+                        action: "change".to_string(),
+                        subsystem: self.subsystem.to_string(),
+                        ..Default::default()
+                    });
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+pub struct UeventListener {
+    pub uevent_tx: broadcast::Sender<Uevent>,
+}
+
+impl UeventListener {
+    pub async fn start(&mut self) {
+        info!("Starting UeventListener");
+        loop {
+            if let Err(e) = self.listen().await {
+                error!("Uevent socket failed: {}", e);
+            }
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn listen(&self) -> std::io::Result<()> {
+        let socket = AsyncFd::with_interest(open_socket()?, Interest::READABLE)?;
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let mut guard = socket.readable().await?;
+            let read = guard.try_io(|socket| {
+                // Safety: buf is as big as the length given
+                let read = unsafe {
+                    libc::recv(
+                        socket.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if read < 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(read as usize)
+                }
+            });
+            match read {
+                Ok(Ok(read)) => {
+                    if let Some(uevent) = Uevent::parse(&buf[..read]) {
+                        debug!("Uevent: {} {}", uevent.action, uevent.devpath);
+                        // Nobody listening is fine
+                        self.uevent_tx.send(uevent).ok();
+                    }
+                }
+                // The kernel dropped messages, subscribers still get the following ones
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    warn!("Uevent socket overflowed");
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+fn open_socket() -> std::io::Result<OwnedFd> {
+    // Safety: plain socket calls, the fd is owned right after it's created
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+
+        let size = RECEIVE_BUFFER as libc::c_int;
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+
+        let mut address: libc::sockaddr_nl = std::mem::zeroed();
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = KERNEL_GROUP;
+        let result = libc::bind(
+            fd,
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BATTERY_CHANGE: &[u8] = b"change@/devices/platform/ff3c0000.i2c/i2c-0/0-0020/rk817-battery/power_supply/rk817-battery\0\
+ACTION=change\0\
+DEVPATH=/devices/platform/ff3c0000.i2c/i2c-0/0-0020/rk817-battery/power_supply/rk817-battery\0\
+SUBSYSTEM=power_supply\0\
+POWER_SUPPLY_NAME=rk817-battery\0\
+POWER_SUPPLY_STATUS=Discharging\0\
+POWER_SUPPLY_CAPACITY=57\0\
+SEQNUM=4127\0";
+
+    const BACKLIGHT_CHANGE: &[u8] =
+        b"change@/devices/platform/backlight_cool/backlight/backlight_cool\0\
+ACTION=change\0\
+DEVPATH=/devices/platform/backlight_cool/backlight/backlight_cool\0\
+SUBSYSTEM=backlight\0\
+SEQNUM=4128\0";
+
+    #[test]
+    fn parses_a_kernel_message() {
+        let uevent = Uevent::parse(BATTERY_CHANGE).unwrap();
+        assert_eq!(uevent.action, "change");
+        assert_eq!(
+            uevent.devpath,
+            "/devices/platform/ff3c0000.i2c/i2c-0/0-0020/rk817-battery/power_supply/rk817-battery"
+        );
+        assert_eq!(uevent.subsystem, "power_supply");
+        assert_eq!(uevent.properties["POWER_SUPPLY_CAPACITY"], "57");
+        assert_eq!(uevent.properties["SEQNUM"], "4127");
+        assert_eq!(uevent.properties.len(), 7);
+    }
+
+    #[test]
+    fn parses_odd_messages() {
+        // Without a trailing null byte, and a value with an equals sign in it
+        let uevent =
+            Uevent::parse(b"add@/devices/virtual/input/input7\0MODALIAS=input:b0000v=1").unwrap();
+        assert_eq!(uevent.action, "add");
+        assert_eq!(uevent.subsystem, "");
+        assert_eq!(uevent.properties["MODALIAS"], "input:b0000v=1");
+
+        // udev's own messages and junk
+        assert_eq!(Uevent::parse(b"libudev\0\xfe\xed\xca\xfe"), None);
+        assert_eq!(Uevent::parse(b""), None);
+        assert_eq!(Uevent::parse(b"\0\0"), None);
+    }
+
+    #[test]
+    fn inject_needs_a_uevent() {
+        let (uevent_tx, mut uevent_rx) = broadcast::channel(4);
+        assert!(inject_uevent(&uevent_tx, BACKLIGHT_CHANGE));
+        assert!(!inject_uevent(&uevent_tx, b"not a uevent"));
+        assert_eq!(uevent_rx.try_recv().unwrap().subsystem, "backlight");
+        assert!(uevent_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn subscription_filters_by_subsystem() {
+        let (uevent_tx, _) = broadcast::channel(8);
+        let mut battery = UeventSubscription::new(&uevent_tx, "power_supply");
+        let mut backlight = UeventSubscription::new(&uevent_tx, "backlight");
+        for message in [BACKLIGHT_CHANGE, BATTERY_CHANGE, BACKLIGHT_CHANGE] {
+            assert!(inject_uevent(&uevent_tx, message));
+        }
+        drop(uevent_tx);
+
+        assert_eq!(battery.next().await, Uevent::parse(BATTERY_CHANGE));
+        assert_eq!(battery.next().await, None);
+        for _ in 0..2 {
+            assert_eq!(backlight.next().await.unwrap().subsystem, "backlight");
+        }
+        assert_eq!(backlight.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn lagging_reads_the_whole_subsystem() {
+        let (uevent_tx, _) = broadcast::channel(2);
+        let mut battery = UeventSubscription::new(&uevent_tx, "power_supply");
+        // Even for missed events of other subsystems, there's no telling which they were
+        for message in [
+            BATTERY_CHANGE,
+            BACKLIGHT_CHANGE,
+            BACKLIGHT_CHANGE,
+            BATTERY_CHANGE,
+        ] {
+            inject_uevent(&uevent_tx, message);
+        }
+
+        let missed = battery.next().await.unwrap();
+        assert_eq!(missed.action, "change");
+        assert_eq!(missed.subsystem, "power_supply");
+        assert_eq!(missed.devpath, "");
+        assert!(missed.properties.is_empty());
+        // Then the ones still in the channel
+        assert_eq!(battery.next().await, Uevent::parse(BATTERY_CHANGE));
+    }
+}
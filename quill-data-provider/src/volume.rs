@@ -1,12 +1,15 @@
 use crate::listener::SocketHandler;
+use crate::process::ManagedProcess;
 use async_trait::async_trait;
 use log::*;
-use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
-};
+use quill_data_provider_lib::SliderMapping;
+use tokio::{process::Command, sync::watch};
 
-pub struct VolumeListener;
+use crate::config::ProviderConfig;
+
+pub struct VolumeListener {
+    pub config_rx: watch::Receiver<ProviderConfig>,
+}
 
 #[async_trait]
 impl SocketHandler for VolumeListener {
@@ -15,38 +18,38 @@ impl SocketHandler for VolumeListener {
     async fn start(&mut self, unix: &mut tokio::net::UnixStream) {
         info!("Starting VolumeListener");
 
-        async fn get_current_volume() -> String {
+        async fn get_current_volume(mapping: &SliderMapping) -> String {
             let output = Command::new("pamixer")
                 .arg("--get-volume-human")
                 .output()
                 .await
                 .expect("Failed to execute pamixer command");
-            String::from_utf8_lossy(&output.stdout)
+            let volume = String::from_utf8_lossy(&output.stdout)
                 .trim()
                 .trim_end_matches('%')
-                .to_string()
+                .to_string();
+            match volume.parse() {
+                Ok(volume) => mapping.to_slider(volume).to_string(),
+                // Like "muted"
+                Err(_) => volume,
+            }
         }
 
-        let mut previous_volume = get_current_volume().await;
+        // Read every time, it follows config reloads
+        let mapping =
+            |config_rx: &watch::Receiver<ProviderConfig>| config_rx.borrow().sliders.volume.clone();
+        let mut previous_volume = get_current_volume(&mapping(&self.config_rx)).await;
         self.send_unix(unix, previous_volume.clone()).await;
 
-        let mut cmd = Command::new("pactl")
-            .arg("subscribe")
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn pactl subscribe command");
-
-        let stdout = cmd.stdout.take().expect("Failed to take stdout");
-        let mut reader = BufReader::new(stdout).lines();
+        let mut pactl = ManagedProcess::new("pactl")
+            .args(["subscribe"])
+            .forward_stdout()
+            .spawn();
 
-        while let Some(line) = reader
-            .next_line()
-            .await
-            .expect("Failed to read line from pactl subscribe")
-        {
+        while let Some(line) = pactl.next_line().await {
             if line.contains("on sink") {
                 // info!("Volume change event detected");
-                let current_volume = get_current_volume().await;
+                let current_volume = get_current_volume(&mapping(&self.config_rx)).await;
                 if previous_volume != current_volume {
                     self.send_unix(unix, current_volume.clone()).await;
                     previous_volume = current_volume;